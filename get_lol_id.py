@@ -1,12 +1,13 @@
 from riotwatcher import LolWatcher
 import sys
 
-if len(sys.argv) != 3:
-    print("Usage: python3 get_lol_id.py <api_key> <puuid>")
+if len(sys.argv) not in (3, 4):
+    print("Usage: python3 get_lol_id.py <api_key> <puuid> [region]")
     sys.exit(1)
 
 api_key = sys.argv[1]
 puuid = sys.argv[2]
+region = sys.argv[3] if len(sys.argv) == 4 else 'jp1'
 # riot_watcher = RiotWatcher(api_key)
 lol_watcher = LolWatcher(api_key)
 
@@ -15,7 +16,7 @@ lol_watcher = LolWatcher(api_key)
 # print(riot_acc['puuid'])
 
 # Get current game information for the summoner
-game_data = lol_watcher.spectator.by_summoner('jp1', puuid)
+game_data = lol_watcher.spectator.by_summoner(region, puuid)
 
 riot_ids = [participant['riotId'] for participant in game_data['participants']]
 print(riot_ids)
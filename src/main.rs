@@ -1,22 +1,66 @@
-use bilistream::config::load_config;
+use bilistream::config::{load_config, load_credentials, Config, FfmpegOptions};
 use bilistream::plugins::{
-    bili_change_live_title, bili_start_live, bili_stop_live, check_area_id_with_title, ffmpeg,
-    get_area_name, get_bili_live_status, get_channel_id, get_channel_name, get_twitch_live_status,
-    get_twitch_live_title, get_youtube_live_title, run_danmaku, select_live,
+    aggregate_alert, area_id_for_topic, bili_change_live_description, bili_change_live_title,
+    bili_check_danmaku, bili_check_login, bili_send_danmaku, bili_send_danmaku_list,
+    bili_start_live, bili_stop_live, bili_update_area, check_area_id_with_title,
+    classify_youtube_restriction, clear_alerts, clear_manual_stop, create_diagnose_bundle,
+    current_stage, escape_markdown, ffmpeg, find_invalid_word, forget_scheduled, get_area_name,
+    get_bili_live_status, get_channel_id, get_channel_name, get_twitch_live_status,
+    get_twitch_live_title, get_youtube_live_title, history_to_csv, load_history,
+    manual_stop_requested, notify, notify_alert, notify_recovered, recent_history,
+    record_bili_status, record_live_end, record_live_start, record_source_quality,
+    record_source_status, refresh_health, remember_scheduled, resolve_twitch_channel,
+    resolve_youtube_channel, run_danmaku, run_doctor, search_areas, select_game_monitor,
+    select_live, select_live_channel, set_manual_stop, set_stage, stop_danmaku, switch_channel,
+    twitch_channel_exists, was_scheduled, weekly_duration, write_overlay, youtube_channel_exists,
+    GameMonitor, Live, OverlayStatus, RestartBackoff, RiotPlayer, Stage, Twitch,
+    GAME_MONITOR_AREAS,
 };
 use chrono::{DateTime, Local};
 use clap::{Arg, Command};
 use proctitle::set_title;
 use regex::Regex;
 use reqwest_middleware::ClientBuilder;
+use serde::Serialize;
 use std::process::Command as StdCommand;
-use std::{error::Error, fs, io, io::BufRead, path::Path, thread, time::Duration};
-use tracing_subscriber::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+use std::{error::Error, fs, io, io::BufRead, path::Path, thread, time::Duration, time::Instant};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, Registry};
+/// Changes the level of the logger installed by [`init_logger`].
+static LOG_LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// ffmpeg restarts of the current rebroadcast, for the history record written on exit.
+static LIVE_RESTARTS: AtomicU32 = AtomicU32::new(0);
+
 fn init_logger() {
-    tracing_subscriber::fmt()
-        .with_timer(fmt::time::ChronoLocal::new("%H:%M:%S".to_string()))
-        .with_span_events(fmt::format::FmtSpan::NONE)
+    let (level, handle) = reload::Layer::new(LevelFilter::INFO);
+    tracing_subscriber::registry()
+        .with(level)
+        .with(
+            fmt::layer()
+                .with_timer(fmt::time::ChronoLocal::new("%H:%M:%S".to_string()))
+                .with_span_events(fmt::format::FmtSpan::NONE),
+        )
         .init();
+    let _ = LOG_LEVEL.set(handle);
+}
+
+/// Applies `LogLevel` from the config, info when unset.
+fn apply_log_level(cfg: &Config) {
+    let Some(handle) = LOG_LEVEL.get() else {
+        return;
+    };
+    let level = cfg
+        .log_level
+        .as_deref()
+        .and_then(|level| level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::INFO);
+    if handle.clone_current() != Some(level) && handle.reload(level).is_ok() {
+        tracing::info!("日志级别: {}", level);
+    }
 }
 async fn run_bilistream(
     config_path: &str,
@@ -26,10 +70,23 @@ async fn run_bilistream(
     init_logger();
     // tracing::info!("bilistream 正在运行");
 
+    set_stage(Stage::Starting);
     let mut cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
+    if let Err(errors) = cfg.validate() {
+        for error in &errors {
+            tracing::error!("{}", error);
+        }
+        return Err(format!("{} 有 {} 处配置错误", config_path, errors.len()).into());
+    }
+    apply_log_level(&cfg);
     let mut old_cfg_title = "".to_string();
     let mut log_once = false;
     let mut no_live = false;
+    let mut manual_live_logged = false;
+    let mut unscheduled_logged = false;
+    let mut outside_window_logged = false;
+    let mut members_only_notified = false;
+    let mut manual_stop_logged = false;
     let mut old_scheduled_start = None;
     let platform = if &cfg.platform == "Youtube" {
         "YT"
@@ -38,24 +95,120 @@ async fn run_bilistream(
     } else {
         return Err("不支持的平台".into());
     };
+    if !cfg.ffmpeg_options.copy {
+        match ffmpeg::video_encoder(&cfg.ffmpeg_options) {
+            Some(encoder) => tracing::info!("转码使用编码器: {}", encoder),
+            None => tracing::warn!(
+                "ffmpeg不支持硬件编码 {}，推流将回退到 -c copy",
+                cfg.ffmpeg_options.hw_accel.as_deref().unwrap_or_default()
+            ),
+        }
+    }
     loop {
         // Check if any ffmpeg or danmaku is running
         if ffmpeg::is_any_ffmpeg_running() {
-            if log_once == false {
+            if !log_once {
                 tracing::info!("一个ffmpeg实例已经在运行。跳过检测循环。");
                 log_once = true;
             }
+            set_stage(Stage::Waiting);
             tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
             continue;
         }
         log_once = false;
-        cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
+        let new_cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
+        match new_cfg.validate() {
+            Ok(()) => {
+                cfg = new_cfg;
+                apply_log_level(&cfg);
+                if let Some(slot) = cfg.scheduled_slot(chrono::Local::now().time()) {
+                    if let Some(title) = &slot.title {
+                        cfg.bililive.title = title.clone();
+                    }
+                }
+            }
+            // Keep running with the last valid config when an edit breaks it
+            Err(errors) => {
+                for error in errors {
+                    tracing::error!("{}，继续使用上次的配置", error);
+                }
+            }
+        }
+        set_stage(if platform == "YT" {
+            Stage::CheckingYoutube
+        } else {
+            Stage::CheckingTwitch
+        });
+        if let Some(live_cfg) = select_live_channel(&cfg).await {
+            cfg = live_cfg;
+        }
 
         let live_info = select_live(cfg.clone()).await?;
-        let (is_live, m3u8_url, title, scheduled_start) = live_info
-            .get_status()
-            .await
-            .unwrap_or((false, None, None, None));
+        let status = live_info.get_status().await;
+        if let Ok((is_live, _, _, _, _)) = &status {
+            record_source_status(&cfg, *is_live);
+        }
+        let (is_live, m3u8_url, title, scheduled_start, members_only) =
+            status.unwrap_or((false, None, None, None, false));
+        if members_only {
+            if !members_only_notified {
+                tracing::info!("{} 正在进行会限直播，无法转播", cfg.youtube.channel_name);
+                if let Err(e) = bili_send_danmaku(&cfg, "该直播为会限，无法转播").await {
+                    tracing::error!("{}", e);
+                }
+                members_only_notified = true;
+            }
+            set_stage(Stage::Waiting);
+            tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+            continue;
+        }
+        members_only_notified = false;
+        if manual_stop_requested(platform) {
+            if is_live {
+                if !manual_stop_logged {
+                    tracing::info!("转播已被手动停止，本场直播结束前不再自动开播");
+                    manual_stop_logged = true;
+                }
+                set_stage(Stage::Waiting);
+                tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+                continue;
+            }
+            clear_manual_stop();
+        }
+        manual_stop_logged = false;
+        if is_live
+            && platform == "YT"
+            && cfg.youtube.only_scheduled
+            && !was_scheduled(&cfg.youtube.channel_id)
+        {
+            if !unscheduled_logged {
+                tracing::info!(
+                    "{} 的直播没有预告，已开启 OnlyScheduled，跳过转播",
+                    cfg.youtube.channel_name
+                );
+                unscheduled_logged = true;
+            }
+            set_stage(Stage::Waiting);
+            tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+            continue;
+        }
+        unscheduled_logged = false;
+        if let Some(window) = cfg.schedule_window.as_ref().filter(|_| is_live) {
+            if !window.contains(chrono::Local::now().time()) {
+                if !outside_window_logged {
+                    tracing::info!(
+                        "当前不在转播时间段（{}-{}）内，跳过转播",
+                        window.start,
+                        window.end
+                    );
+                    outside_window_logged = true;
+                }
+                set_stage(Stage::Waiting);
+                tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+                continue;
+            }
+        }
+        outside_window_logged = false;
         if is_live {
             tracing::info!(
                 "{} 正在 {} 直播, 标题:\n          {}",
@@ -68,151 +221,326 @@ async fn run_bilistream(
                 title.unwrap()
             );
             no_live = false;
-            if platform == "YT" {
-                let live_topic = if let Ok(topic) =
-                    get_live_topic(platform, Some(&cfg.youtube.channel_id)).await
-                {
-                    topic
-                } else {
-                    get_live_title(platform, Some(&cfg.youtube.channel_id)).await?
-                };
-                cfg.bililive.area_v2 = check_area_id_with_title(&live_topic, cfg.bililive.area_v2);
-                if cfg.bililive.area_v2 == 240 && !cfg.youtube.channel_id.contains("Kamito") {
-                    cfg.bililive.area_v2 = 0
-                };
-            } else {
-                let live_title = get_live_title(platform, Some(&cfg.twitch.channel_id)).await?;
-                cfg.bililive.area_v2 = check_area_id_with_title(&live_title, cfg.bililive.area_v2);
-                if cfg.bililive.area_v2 == 240 && !cfg.twitch.channel_id.contains("Kamito") {
-                    cfg.bililive.area_v2 = 0
-                };
-            }
+            set_stage(Stage::CheckingConflict);
+            cfg.bililive.area_v2 = detect_area(&cfg, platform).await?;
             if cfg.bililive.area_v2 == 0 {
                 tracing::info!("标题包含的直播分区不支持,等待10min后重新检测");
                 // 等待10min后重新检测
+                set_stage(Stage::Waiting);
                 tokio::time::sleep(Duration::from_secs(600)).await;
                 continue;
             }
+            set_stage(Stage::CheckingBili);
             let (is_live, title, area_id) = get_bili_live_status(cfg.bililive.room).await?;
+            record_bili_status(&cfg, is_live);
+            // A title without the rebroadcast prefix means the live was started by hand
+            if is_live && cfg.bililive.respect_manual_live && !title.contains("【转播】") {
+                if !manual_live_logged {
+                    tracing::info!("B站正在手动直播（标题：{}），暂不接管", title);
+                    manual_live_logged = true;
+                }
+                set_stage(Stage::Waiting);
+                tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
+                continue;
+            }
+            manual_live_logged = false;
+            let bili_title = cfg.bililive.bili_title();
             if !is_live {
                 tracing::info!("B站未直播");
                 let area_name = get_area_name(cfg.bililive.area_v2);
+                set_stage(Stage::StartingLive);
                 bili_start_live(&cfg).await?;
-                if title != cfg.bililive.title {
+                if title != bili_title {
                     bili_change_live_title(&cfg).await?;
                 }
                 tracing::info!(
                     "B站已开播，标题为 {}，分区为 {} （ID: {}）",
-                    cfg.bililive.title,
+                    bili_title,
                     area_name.unwrap(),
                     cfg.bililive.area_v2
                 );
+                if !cfg.bililive.opening_danmaku.is_empty() {
+                    let cfg = cfg.clone();
+                    let channel_name = match platform {
+                        "TW" => cfg.twitch.channel_name.clone(),
+                        _ => cfg.youtube.channel_name.clone(),
+                    };
+                    tokio::spawn(async move {
+                        bili_send_danmaku_list(&cfg, &cfg.bililive.opening_danmaku, &channel_name)
+                            .await;
+                    });
+                }
             } else {
                 // If configuration changed, stop Bilibili live
                 if cfg.bililive.area_v2 != area_id {
                     let to_area_name = get_area_name(cfg.bililive.area_v2);
                     let area_name = get_area_name(area_id);
-                    if area_name.is_some() && to_area_name.is_some() {
-                        tracing::info!(
-                            "分区改变（{}->{}），请调整分区",
-                            area_name.unwrap(),
-                            to_area_name.unwrap()
-                        );
+                    if let (Some(area_name), Some(to_area_name)) = (area_name, to_area_name) {
+                        tracing::info!("分区改变（{}->{}），请调整分区", area_name, to_area_name);
                     }
                     // bili_stop_live(&cfg).await?;
                     // bili_start_live(&cfg).await?;
                     bili_change_live_title(&cfg).await?;
-                    tracing::info!("已更换转播频道，标题：{}", cfg.bililive.title);
+                    tracing::info!("已更换转播频道，标题：{}", bili_title);
                     log_once = false;
                 }
                 // 如果标题改变，则变更B站直播标题
-                if title != cfg.bililive.title {
+                if title != bili_title {
                     bili_change_live_title(&cfg).await?;
-                    tracing::info!("B站直播标题变更 （{}->{}）", title, cfg.bililive.title);
+                    tracing::info!("B站直播标题变更 （{}->{}）", title, bili_title);
                 }
             }
 
-            if cfg.bililive.area_v2 == 86 {
-                let puuid = get_puuid_from_file(&cfg.youtube.channel_name)?;
-                monitor_lol_game(puuid)?;
-            }
+            start_game_monitor(&cfg)?;
 
-            // Execute ffmpeg with platform-specific locks
-            ffmpeg(
-                cfg.bililive.bili_rtmp_url.clone(),
-                cfg.bililive.bili_rtmp_key.clone(),
-                m3u8_url.clone().unwrap(),
-                cfg.proxy.clone(),
-                ffmpeg_log_level,
-                platform,
+            if cfg.bililive.annotate_source {
+                let description = format!("转播来源：{}", source_url(&cfg, platform));
+                if let Err(e) = bili_change_live_description(&cfg, &description).await {
+                    tracing::error!("{}", e);
+                }
+            }
+            write_overlay(
+                &cfg,
+                &OverlayStatus {
+                    channel_name: Some(match platform {
+                        "TW" => &cfg.twitch.channel_name,
+                        _ => &cfg.youtube.channel_name,
+                    }),
+                    platform: &cfg.platform,
+                    area_name: get_area_name(cfg.bililive.area_v2),
+                    streaming: true,
+                },
             );
-            // avoid ffmpeg exit errorly and the live is still running, restart ffmpeg
+            if let Err(e) = record_live_start(
+                match platform {
+                    "TW" => &cfg.twitch.channel_name,
+                    _ => &cfg.youtube.channel_name,
+                },
+                platform,
+                cfg.bililive.area_v2,
+                get_area_name(cfg.bililive.area_v2).unwrap_or("未知"),
+            ) {
+                tracing::error!("写入转播记录失败: {}", e);
+            }
+            notify(
+                &cfg,
+                &format!(
+                    "*{}* 开始转播\n分区: {}\n{}",
+                    escape_markdown(match platform {
+                        "TW" => &cfg.twitch.channel_name,
+                        _ => &cfg.youtube.channel_name,
+                    }),
+                    get_area_name(cfg.bililive.area_v2).unwrap_or("未知"),
+                    escape_markdown(&source_url(&cfg, platform))
+                ),
+            )
+            .await;
+            set_stage(Stage::Streaming);
+            let mut restarts = 0;
+            LIVE_RESTARTS.store(0, Ordering::Relaxed);
+            let mut backoff = RestartBackoff::default();
+            let ffmpeg_options = ffmpeg_options_for(&cfg, platform);
+            ffmpeg::reset_quality_level();
+            let mut m3u8_url = m3u8_url.unwrap();
+            // When the Bilibili live was found cut off while the source kept going
+            let mut gap: Option<(DateTime<Local>, Option<ffmpeg::GapRecording>)> = None;
+            let live_key = match platform {
+                "TW" => &cfg.twitch.channel_id,
+                _ => &cfg.youtube.channel_id,
+            };
+            let quality = ffmpeg::probe_source_quality(live_key, &m3u8_url, cfg.proxy.as_deref());
+            match &quality {
+                Some(quality) => tracing::info!("源：{}", quality),
+                None => tracing::warn!("无法获取源的分辨率和码率"),
+            }
+            record_source_quality(&cfg, quality.map(|quality| quality.to_string()));
+
             loop {
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                if cfg.bililive.area_v2 == 86 {
-                    let puuid = get_puuid_from_file(&cfg.youtube.channel_name)?;
-                    monitor_lol_game(puuid)?;
+                let started_at = Instant::now();
+                // Execute ffmpeg with platform-specific locks
+                let streaming = tokio::task::spawn_blocking({
+                    let cfg = cfg.clone();
+                    let ffmpeg_log_level = ffmpeg_log_level.to_string();
+                    let ffmpeg_options = ffmpeg_options.clone();
+                    move || {
+                        ffmpeg(
+                            cfg.bililive.bili_rtmp_url.clone(),
+                            cfg.bililive.bili_rtmp_key.clone(),
+                            m3u8_url,
+                            cfg.proxy.clone(),
+                            &ffmpeg_log_level,
+                            platform,
+                            &ffmpeg_options,
+                        )
+                    }
+                });
+                if watch_source_while_streaming(&streaming, config_path, &mut cfg, &*live_info)
+                    .await
+                {
+                    let _ = streaming.await;
+                    break;
+                }
+                let _ = streaming.await;
+                if manual_stop_requested(platform) {
+                    tracing::info!("转播已被手动停止");
+                    break;
+                }
+                if ffmpeg::take_quality_downgraded() {
+                    if let Some(message) =
+                        aggregate_alert("danmaku_quality", "网络不佳，已自动降低画质")
+                    {
+                        if let Err(e) = bili_send_danmaku(&cfg, &message).await {
+                            tracing::error!("{}", e);
+                        }
+                    }
                 }
-                let (current_is_live, new_m3u8_url, _, _) = live_info
+
+                // avoid ffmpeg exit errorly and the live is still running, restart ffmpeg
+                let wait = Duration::from_secs(cfg.min_ffmpeg_restart_interval)
+                    .saturating_sub(started_at.elapsed())
+                    .max(backoff.next_delay(started_at.elapsed()));
+                tracing::info!("ffmpeg已退出，{}秒后重启", wait.as_secs());
+                tokio::time::sleep(wait).await;
+                start_game_monitor(&cfg)?;
+                let (current_is_live, new_m3u8_url, _, _, _) = live_info
                     .get_status()
                     .await
-                    .unwrap_or((false, None, None, None));
+                    .unwrap_or((false, None, None, None, false));
                 if !current_is_live {
                     break;
                 }
+                let new_m3u8_url = new_m3u8_url.unwrap();
+                // The source is still live, so a closed Bilibili live was cut off
+                match get_bili_live_status(cfg.bililive.room).await {
+                    Ok((false, _, _)) => {
+                        tracing::warn!("B站直播已被关闭，可能被切断");
+                        let reopened = if cfg.bililive.auto_reopen_on_external_stop {
+                            match bili_start_live(&cfg).await {
+                                Ok(()) => {
+                                    tracing::info!("B站直播已重新开播，继续转播");
+                                    notify_alert(
+                                        &cfg,
+                                        "bili_reopened",
+                                        "B站直播被外部关闭，已自动重新开播",
+                                    )
+                                    .await;
+                                    true
+                                }
+                                Err(e) => {
+                                    tracing::error!("重新开播失败: {}", e);
+                                    notify_alert(
+                                        &cfg,
+                                        "bili_cut",
+                                        "B站直播已被关闭，重新开播失败，请尽快检查",
+                                    )
+                                    .await;
+                                    false
+                                }
+                            }
+                        } else {
+                            notify_alert(
+                                &cfg,
+                                "bili_cut",
+                                "B站直播已被关闭，可能被切断，请尽快检查",
+                            )
+                            .await;
+                            false
+                        };
+                        if !reopened && gap.is_none() {
+                            gap = Some((Local::now(), start_gap_recording(&cfg, &new_m3u8_url)));
+                        }
+                    }
+                    Ok((true, _, _)) => {
+                        notify_recovered(&cfg, "bili_cut", "B站直播已恢复").await;
+                        if let Some((started, recording)) = gap.take() {
+                            report_gap(&cfg, started, recording, true).await;
+                        }
+                    }
+                    Err(_) => {}
+                }
                 // let (is_live, _, _) = get_bili_live_status(cfg.bililive.room).await?;
                 // if !is_live {
                 //     bili_start_live(&cfg).await?;
                 // }
-                ffmpeg(
-                    cfg.bililive.bili_rtmp_url.clone(),
-                    cfg.bililive.bili_rtmp_key.clone(),
-                    new_m3u8_url.clone().unwrap(),
-                    cfg.proxy.clone(),
-                    ffmpeg_log_level,
-                    platform,
-                );
+                restarts += 1;
+                LIVE_RESTARTS.store(restarts, Ordering::Relaxed);
+                m3u8_url = new_m3u8_url;
+            }
+            if let Some((started, recording)) = gap.take() {
+                report_gap(&cfg, started, recording, false).await;
             }
+            clear_alerts();
 
-            tracing::info!(
-                "{} 直播结束",
-                match platform {
-                    "TW" => &cfg.twitch.channel_name,
-                    "YT" => &cfg.youtube.channel_name,
-                    _ => "未知平台",
-                }
+            let channel_name = match platform {
+                "TW" => &cfg.twitch.channel_name,
+                "YT" => &cfg.youtube.channel_name,
+                _ => "未知平台",
+            };
+            tracing::info!("{} 直播结束", channel_name);
+            notify(
+                &cfg,
+                &format!("*{}* 直播结束，转播已停止", escape_markdown(channel_name)),
+            )
+            .await;
+            if platform == "YT" {
+                forget_scheduled(&cfg.youtube.channel_id);
+            }
+            if let Err(e) = record_live_end(platform, restarts) {
+                tracing::error!("写入转播记录失败: {}", e);
+            }
+            write_overlay(
+                &cfg,
+                &OverlayStatus {
+                    channel_name: None,
+                    platform: &cfg.platform,
+                    area_name: None,
+                    streaming: false,
+                },
             );
+            if !cfg.bililive.closing_danmaku.is_empty() {
+                let cfg = cfg.clone();
+                let channel_name = channel_name.to_string();
+                tokio::spawn(async move {
+                    bili_send_danmaku_list(&cfg, &cfg.bililive.closing_danmaku, &channel_name)
+                        .await;
+                });
+            }
             if cfg.bililive.enable_danmaku_command {
                 thread::spawn(move || run_danmaku(platform));
             }
         } else {
+            // The next live of the channel is probed again
+            ffmpeg::clear_source_quality();
             // 计划直播(预告窗)
-            if scheduled_start.is_some() {
+            if let Some(scheduled_start) = scheduled_start {
+                if cfg.youtube.only_scheduled && !was_scheduled(&cfg.youtube.channel_id) {
+                    remember_scheduled(&cfg.youtube.channel_id);
+                }
                 let mut diff = chrono::Duration::seconds(0);
-                if old_scheduled_start.is_some() {
-                    diff = old_scheduled_start.unwrap() - scheduled_start.unwrap();
+                if let Some(old_scheduled_start) = old_scheduled_start {
+                    diff = old_scheduled_start - scheduled_start;
                 }
                 if !old_cfg_title.contains(&cfg.bililive.title) || diff.num_hours() > 2 {
                     let live_title =
                         get_live_title(platform, Some(&cfg.youtube.channel_id)).await?;
-                    if live_title != "" && live_title != "空" {
+                    if !live_title.is_empty() && live_title != "空" {
                         tracing::info!(
                             "{} 未直播，计划于 {} 开始，标题：\n          {}",
                             cfg.youtube.channel_name,
-                            scheduled_start.unwrap().format("%Y-%m-%d %H:%M:%S"), // Format the start time
+                            scheduled_start.format("%Y-%m-%d %H:%M:%S"), // Format the start time
                             live_title
                         );
                     } else {
                         tracing::info!(
                             "{} 未直播，计划于 {} 开始",
                             cfg.youtube.channel_name,
-                            scheduled_start.unwrap().format("%Y-%m-%d %H:%M:%S")
+                            scheduled_start.format("%Y-%m-%d %H:%M:%S")
                         );
                     }
-                    old_scheduled_start = scheduled_start;
+                    old_scheduled_start = Some(scheduled_start);
                 }
             } else {
-                if no_live == false {
+                if !no_live {
                     tracing::info!(
                         "{} 未直播",
                         match platform {
@@ -224,15 +552,310 @@ async fn run_bilistream(
                     no_live = true;
                 };
             }
+            if platform == "TW" && cfg.twitch.fill_with_clips {
+                if let Err(e) = fill_with_clips(&cfg, &*live_info, ffmpeg_log_level).await {
+                    tracing::error!("高光填充失败: {}", e);
+                }
+            }
             if cfg.bililive.enable_danmaku_command {
                 thread::spawn(move || run_danmaku(platform));
             }
             old_cfg_title = cfg.bililive.title.clone();
+            set_stage(Stage::Waiting);
             tokio::time::sleep(Duration::from_secs(cfg.interval)).await;
         }
     }
 }
 
+/// Number of checks in a row that must agree on a new area before the room is moved,
+/// so a title edited back and forth does not flip the area each time.
+const AREA_CHANGE_CONFIRMATIONS: u32 = 3;
+
+/// Number of checks in a row that must report the source offline before a running
+/// push is stopped, so a yt-dlp or Holodex hiccup does not cut a healthy stream.
+const SOURCE_END_CONFIRMATIONS: u32 = 3;
+
+/// Works out the Bilibili area from the source topic or title, 0 if unsupported.
+async fn detect_area(cfg: &Config, platform: &str) -> Result<u64, Box<dyn Error>> {
+    let slot = cfg.scheduled_slot(chrono::Local::now().time());
+    let fallback_area = slot.map_or(cfg.bililive.area_v2, |slot| slot.area_id);
+    let mut area_id = if platform == "YT" {
+        let live_topic = get_live_topic(platform, Some(&cfg.youtube.channel_id))
+            .await
+            .ok();
+        match live_topic.as_deref().and_then(area_id_for_topic) {
+            Some(area_id) => area_id,
+            None => {
+                let live_title = get_live_title(platform, Some(&cfg.youtube.channel_id)).await?;
+                // A topic missing from the table can still match a keyword, e.g. "Apex_Legends"
+                let text = format!("{} {}", live_topic.unwrap_or_default(), live_title);
+                check_area_id_with_title(&text, fallback_area)
+            }
+        }
+    } else {
+        let live_title = get_live_title(platform, Some(&cfg.twitch.channel_id)).await?;
+        check_area_id_with_title(&live_title, fallback_area)
+    };
+    let (channel_name, channel_id) = match platform {
+        "TW" => (&cfg.twitch.channel_name, &cfg.twitch.channel_id),
+        _ => (&cfg.youtube.channel_name, &cfg.youtube.channel_id),
+    };
+    if !cfg.area_allowed(area_id, channel_name, channel_id) {
+        tracing::debug!(
+            "{} 不允许使用分区 {}（AreaRestrictions）",
+            channel_name,
+            get_area_name(area_id).unwrap_or("未知")
+        );
+        area_id = 0
+    };
+    // The programme is set by hand, so it is not subject to the rule above
+    if let Some(slot) = slot.filter(|_| !cfg.prefer_source_area) {
+        area_id = slot.area_id;
+    }
+    Ok(area_id)
+}
+
+/// Starts recording the source when `BiliLive.GapRecordDir` is set.
+fn start_gap_recording(cfg: &Config, m3u8_url: &str) -> Option<ffmpeg::GapRecording> {
+    let dir = cfg.bililive.gap_record_dir.as_deref()?;
+    match ffmpeg::start_gap_recording(dir, m3u8_url, cfg.proxy.as_deref()) {
+        Ok(recording) => {
+            tracing::info!("转播中断期间录制源直播到 {}", recording.path.display());
+            Some(recording)
+        }
+        Err(e) => {
+            tracing::error!("录制源直播失败: {}", e);
+            None
+        }
+    }
+}
+
+/// Tells which part of the source was not rebroadcast, once the Bilibili live is
+/// back (`recovered`) or the source has ended while it was still cut off.
+async fn report_gap(
+    cfg: &Config,
+    started: DateTime<Local>,
+    recording: Option<ffmpeg::GapRecording>,
+    recovered: bool,
+) {
+    let minutes = (Local::now() - started).num_minutes();
+    let mut message = if recovered {
+        format!(
+            "转播已恢复，{} 起约 {} 分钟的内容没有转到",
+            started.format("%H:%M"),
+            minutes
+        )
+    } else {
+        format!(
+            "转播自 {} 中断后未恢复，约 {} 分钟的内容没有转到",
+            started.format("%H:%M"),
+            minutes
+        )
+    };
+    if let Some(recording) = recording {
+        message.push_str(&format!("，已录制到 {}", recording.path.display()));
+    }
+    tracing::warn!("{}", message);
+    notify(cfg, &escape_markdown(&message)).await;
+}
+
+/// Keeps checking the source while ffmpeg pushes it, so that an ended source or a
+/// channel switched in the config stops ffmpeg without waiting for it to fail.
+/// A game change on the source, or the next `AreaSchedule` slot, moves the Bilibili
+/// area (and title) along with it.
+/// Returns true when ffmpeg was stopped because the stream should end.
+async fn watch_source_while_streaming(
+    streaming: &tokio::task::JoinHandle<()>,
+    config_path: &str,
+    cfg: &mut Config,
+    live_info: &dyn Live,
+) -> bool {
+    let platform = if cfg.platform == "Twitch" { "TW" } else { "YT" };
+    let mut pending_area = (cfg.bililive.area_v2, 0);
+    let mut offline_checks = 0;
+    let mut last_check = Instant::now();
+    while !streaming.is_finished() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        if last_check.elapsed() < Duration::from_secs(cfg.interval) {
+            continue;
+        }
+        last_check = Instant::now();
+        if let Ok(new_cfg) = load_config(Path::new(config_path), Path::new("cookies.json")) {
+            let channel_id = match cfg.platform.as_str() {
+                "Twitch" => &cfg.twitch.channel_id,
+                _ => &cfg.youtube.channel_id,
+            };
+            let switched = !new_cfg
+                .candidate_channels()
+                .iter()
+                .any(|channel| &channel.channel_id == channel_id);
+            if switched {
+                tracing::info!("配置中的转播频道已更换，停止当前推流");
+                return ffmpeg::stop_ffmpeg();
+            }
+        }
+        // A single offline or failed check is not treated as the end, the restart loop
+        // has the final say
+        match live_info.get_status().await {
+            Ok((false, _, _, _, _)) => {
+                record_source_status(cfg, false);
+                offline_checks += 1;
+                if offline_checks >= SOURCE_END_CONFIRMATIONS {
+                    tracing::info!("源直播已结束，停止推流");
+                    return ffmpeg::stop_ffmpeg();
+                }
+            }
+            Ok(_) => {
+                offline_checks = 0;
+                record_source_status(cfg, true);
+            }
+            Err(_) => refresh_health(cfg),
+        }
+        let scheduled_title = cfg
+            .scheduled_slot(chrono::Local::now().time())
+            .and_then(|slot| slot.title.clone());
+        if let Some(title) = scheduled_title.filter(|title| *title != cfg.bililive.title) {
+            let old_title = std::mem::replace(&mut cfg.bililive.title, title);
+            match bili_change_live_title(cfg).await {
+                Ok(()) => tracing::info!(
+                    "节目表时间到，B站直播标题变更 （{}->{}）",
+                    old_title,
+                    cfg.bililive.title
+                ),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    cfg.bililive.title = old_title;
+                }
+            }
+        }
+        match detect_area(cfg, platform).await {
+            Ok(area_id) if area_id != 0 && area_id != cfg.bililive.area_v2 => {
+                if pending_area.0 == area_id {
+                    pending_area.1 += 1;
+                } else {
+                    pending_area = (area_id, 1);
+                }
+                if pending_area.1 < AREA_CHANGE_CONFIRMATIONS {
+                    continue;
+                }
+                match bili_update_area(cfg, area_id).await {
+                    Ok(()) => {
+                        tracing::info!(
+                            "源切换了游戏，B站分区已更新（{}->{}）",
+                            get_area_name(cfg.bililive.area_v2).unwrap_or("未知"),
+                            get_area_name(area_id).unwrap_or("未知")
+                        );
+                        cfg.bililive.area_v2 = area_id;
+                    }
+                    Err(e) => tracing::error!("{}", e),
+                }
+            }
+            _ => pending_area = (cfg.bililive.area_v2, 0),
+        }
+    }
+    false
+}
+
+/// Pushes recent clips of the offline Twitch channel one after another while the
+/// Bilibili live is on, and returns as soon as the channel goes live again.
+async fn fill_with_clips(
+    cfg: &Config,
+    live_info: &dyn Live,
+    ffmpeg_log_level: &str,
+) -> Result<(), Box<dyn Error>> {
+    let (bili_is_live, _, _) = get_bili_live_status(cfg.bililive.room).await?;
+    if !bili_is_live {
+        return Ok(());
+    }
+    let twitch = Twitch::new(
+        &cfg.twitch.channel_id,
+        cfg.twitch.oauth_token.clone(),
+        ClientBuilder::new(reqwest::Client::new()).build(),
+        cfg.twitch.proxy_region.clone(),
+        cfg.twitch.disable_ads,
+        cfg.twitch.allow_rerun,
+    );
+    let clips = twitch.get_recent_clips().await?;
+    if clips.is_empty() {
+        tracing::info!("{} 没有近期高光，跳过填充", cfg.twitch.channel_name);
+        return Ok(());
+    }
+    tracing::info!("使用 {} 个近期高光填充直播间", clips.len());
+    // Clips are files, which ffmpeg would push as fast as it can read them, and
+    // follow each other without a placeholder in between
+    let options = FfmpegOptions {
+        re: true,
+        placeholder: None,
+        ..ffmpeg_options_for(cfg, "TW")
+    };
+    for slug in clips {
+        if let Ok((true, _, _, _, _)) = live_info.get_status().await {
+            tracing::info!("{} 重新开播，停止高光填充", cfg.twitch.channel_name);
+            break;
+        }
+        let clip_url = match twitch.get_clip_url(&slug) {
+            Ok(url) => url,
+            Err(e) => {
+                tracing::warn!("获取高光 {} 失败: {}", slug, e.to_string().trim());
+                continue;
+            }
+        };
+        let cfg = cfg.clone();
+        let ffmpeg_log_level = ffmpeg_log_level.to_string();
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || {
+            ffmpeg(
+                cfg.bililive.bili_rtmp_url.clone(),
+                cfg.bililive.bili_rtmp_key.clone(),
+                clip_url,
+                cfg.proxy.clone(),
+                &ffmpeg_log_level,
+                "TW",
+                &options,
+            )
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns the link to the source live page of the current channel.
+fn source_url(cfg: &Config, platform: &str) -> String {
+    match platform {
+        "TW" => format!("https://www.twitch.tv/{}", cfg.twitch.channel_id),
+        _ => format!(
+            "https://www.youtube.com/channel/{}/live",
+            cfg.youtube.channel_id
+        ),
+    }
+}
+
+/// Returns the ffmpeg options with the ticker and watermark placeholders filled in for
+/// the current channel.
+fn ffmpeg_options_for(cfg: &Config, platform: &str) -> FfmpegOptions {
+    let mut options = cfg.ffmpeg_options.clone();
+    options.command_template = cfg.ffmpeg_command_template.clone();
+    if cfg.bililive.push_protocol() == "srt" {
+        options.srt_latency = Some(cfg.bililive.srt_latency);
+    }
+    let channel_name = match platform {
+        "TW" => &cfg.twitch.channel_name,
+        _ => &cfg.youtube.channel_name,
+    };
+    let fill = |text: &str| {
+        text.replace("{channel}", channel_name)
+            .replace("{platform}", &cfg.platform)
+            .replace("{url}", &source_url(cfg, platform))
+    };
+    if let Some(ticker) = options.ticker.as_mut() {
+        ticker.text = fill(&ticker.text);
+    }
+    if let Some(watermark) = options.watermark.as_mut() {
+        watermark.text = fill(&watermark.text);
+    }
+    options
+}
+
 async fn get_live_topic(
     platform: &str,
     channel_id: Option<&str>,
@@ -252,9 +875,12 @@ async fn get_live_topic(
                 "https://holodex.net/api/v2/users/live?channels={}",
                 channel_id
             );
+            let Some(holodex_api_key) = cfg.holodex_api_key.clone() else {
+                return Err("未设置HolodexApiKey，无法获取直播分区".into());
+            };
             let response = client
                 .get(&url)
-                .header("X-APIKEY", cfg.holodex_api_key.clone().unwrap())
+                .header("X-APIKEY", holodex_api_key)
                 .send()
                 .await?;
 
@@ -303,25 +929,66 @@ async fn get_live_topic(
     }
 }
 
+/// Live status of the Bilibili room, see [`LiveStatusReport`].
+#[derive(Debug, Serialize)]
+struct BiliStatusReport {
+    is_live: bool,
+    title: Option<String>,
+    area_id: Option<u64>,
+    area_name: Option<String>,
+}
+
+/// Live status of a YouTube or Twitch channel, see [`LiveStatusReport`].
+#[derive(Debug, Serialize)]
+struct SourceStatusReport {
+    channel_name: String,
+    channel_id: String,
+    /// "live", "upcoming" or "offline".
+    status: &'static str,
+    title: Option<String>,
+    scheduled_start: Option<DateTime<Local>>,
+    /// Lives of the channel that cannot be rebroadcast, e.g. members-only ones.
+    restrictions: Vec<String>,
+}
+
+impl SourceStatusReport {
+    fn offline(channel_name: String, channel_id: &str) -> Self {
+        SourceStatusReport {
+            channel_name,
+            channel_id: channel_id.to_string(),
+            status: "offline",
+            title: None,
+            scheduled_start: None,
+            restrictions: Vec::new(),
+        }
+    }
+}
+
+/// What `get-live-status` found, printed as text or, with `--json`, as JSON.
+#[derive(Debug, Default, Serialize)]
+struct LiveStatusReport {
+    bilibili: Option<BiliStatusReport>,
+    youtube: Option<SourceStatusReport>,
+    twitch: Option<SourceStatusReport>,
+}
+
 async fn get_live_status(
     platform: &str,
     channel_id: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<LiveStatusReport, Box<dyn std::error::Error>> {
+    let mut report = LiveStatusReport::default();
     match platform {
         "bilibili" => {
             let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
             let (is_live, title, area_id) = get_bili_live_status(cfg.bililive.room).await?;
-            if is_live {
-                let area_name = get_area_name(area_id);
-                println!(
-                    "B站直播状态: 直播中, 标题: {}, 分区: {} （ID: {}）",
-                    title,
-                    area_name.unwrap(),
-                    area_id
-                );
-            } else {
-                println!("B站直播状态: 未直播");
-            }
+            report.bilibili = Some(BiliStatusReport {
+                is_live,
+                title: is_live.then_some(title),
+                area_id: is_live.then_some(area_id),
+                area_name: is_live
+                    .then(|| get_area_name(area_id).map(str::to_string))
+                    .flatten(),
+            });
         }
         "YT" => {
             let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
@@ -330,10 +997,10 @@ async fn get_live_status(
             } else {
                 &cfg.youtube.channel_id
             };
-            let mut channel_name = get_channel_name("YT", channel_id).unwrap();
-            if channel_name.is_none() {
-                channel_name = Some(cfg.youtube.channel_name.clone());
-            }
+            let channel_name = get_channel_name("YT", channel_id)
+                .unwrap()
+                .unwrap_or_else(|| cfg.youtube.channel_name.clone());
+            let mut youtube = SourceStatusReport::offline(channel_name.clone(), channel_id);
             let client = reqwest::Client::new();
             let url = format!(
                 "https://holodex.net/api/v2/users/live?channels={}",
@@ -346,100 +1013,67 @@ async fn get_live_status(
                 .await?;
             if response.status().is_success() {
                 let videos: Vec<serde_json::Value> = response.json().await?;
-                // println!("{:?}", videos);
-                if !videos.is_empty() {
-                    let mut vid = videos.last().unwrap();
-                    let mut flag = false;
-                    for video in videos.iter().rev() {
-                        let cname = video.get("channel");
-                        if cname
-                            .unwrap()
-                            .get("name")
-                            .unwrap()
-                            .as_str()
-                            .unwrap()
-                            .replace(" ", "")
-                            .contains(channel_name.as_ref().unwrap())
+                let mut vid = None;
+                for video in videos.iter().rev() {
+                    let cname = video.get("channel");
+                    if cname
+                        .unwrap()
+                        .get("name")
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .replace(" ", "")
+                        .contains(&channel_name)
+                    {
+                        let topic_id = video.get("topic_id").unwrap();
+                        if let Some(restriction) =
+                            classify_youtube_restriction(topic_id.as_str().unwrap())
                         {
-                            let topic_id = video.get("topic_id").unwrap();
-                            if topic_id.as_str().unwrap().contains("membersonly") {
-                                // tracing::info!("频道 {} 正在进行会限直播", channel_name);
-                                println!(
-                                    "频道 {} 正在进行会限直播",
-                                    channel_name.as_ref().unwrap()
-                                );
-                            } else {
-                                vid = video;
-                                flag = true;
-                                break;
-                            }
+                            youtube
+                                .restrictions
+                                .push(restriction.describe().to_string());
+                        } else {
+                            vid = Some(video);
+                            break;
                         }
                     }
-                    if flag {
-                        let status = vid.get("status").unwrap();
-                        if status == "upcoming" {
-                            let start_time_str = vid
-                                .get("start_scheduled")
-                                .and_then(|v| v.as_str())
-                                .ok_or("start_scheduled 不存在")?;
-                            // 将时间字符串转换为DateTime<Local>
-                            let start_time = DateTime::parse_from_rfc3339(&start_time_str)?
-                                .with_timezone(&Local);
-                            let title = vid.get("title").unwrap();
-                            if title != "" {
-                                println!(
-                                    "{} 计划于 {} 开始 YouTube 直播, 标题: {}",
-                                    channel_name.as_ref().unwrap(),
-                                    start_time,
-                                    title
-                                );
-                            } else {
-                                println!(
-                                    "{} 计划于 {} 开始 YouTube 直播",
-                                    channel_name.as_ref().unwrap(),
-                                    start_time
-                                );
-                            }
-                        } else if status == "live" {
-                            let title = vid.get("title").unwrap();
-                            let channel_id =
-                                get_channel_id("TW", channel_name.as_ref().unwrap()).unwrap();
-                            if channel_id.is_some() {
-                                if !get_twitch_live_status(channel_id.as_ref().unwrap())
-                                    .await
-                                    .unwrap()
-                                {
-                                    println!(
-                                        "{} 在 YouTube 直播中, 标题: {}",
-                                        channel_name.as_ref().unwrap(),
-                                        title
-                                    );
-                                } else {
-                                    println!(
-                                        "{} 在 Twitch 直播中, 标题: {}",
-                                        channel_name.as_ref().unwrap(),
-                                        title
-                                    );
-                                }
-                            } else {
-                                println!(
-                                    "{} 在 YouTube 直播中, 标题: {}",
-                                    channel_name.as_ref().unwrap(),
-                                    title
-                                );
+                }
+                if let Some(vid) = vid {
+                    let status = vid.get("status").unwrap();
+                    let title = vid
+                        .get("title")
+                        .and_then(|v| v.as_str())
+                        .filter(|title| !title.is_empty())
+                        .map(str::to_string);
+                    if status == "upcoming" {
+                        let start_time_str = vid
+                            .get("start_scheduled")
+                            .and_then(|v| v.as_str())
+                            .ok_or("start_scheduled 不存在")?;
+                        // 将时间字符串转换为DateTime<Local>
+                        let start_time =
+                            DateTime::parse_from_rfc3339(start_time_str)?.with_timezone(&Local);
+                        youtube.status = "upcoming";
+                        youtube.title = title;
+                        youtube.scheduled_start = Some(start_time);
+                    } else if status == "live" {
+                        youtube.status = "live";
+                        youtube.title = title;
+                        // Holodex also lists simulcasts, which go to Twitch when it is live there
+                        if let Some(twitch_id) = get_channel_id("TW", &channel_name).unwrap() {
+                            if get_twitch_live_status(&twitch_id).await.unwrap() {
+                                let mut twitch =
+                                    SourceStatusReport::offline(channel_name.clone(), &twitch_id);
+                                twitch.status = "live";
+                                twitch.title = youtube.title.take();
+                                youtube.status = "offline";
+                                report.twitch = Some(twitch);
                             }
-                        } else {
-                            let channel_name = cfg.youtube.channel_name;
-                            println!("{} 未直播", channel_name);
                         }
-                    } else {
-                        let channel_name = cfg.youtube.channel_name;
-                        println!("{} 未直播", channel_name)
                     }
-                } else {
-                    println!("{} 未直播", cfg.youtube.channel_name);
                 }
             }
+            report.youtube = Some(youtube);
         }
         "TW" => {
             let cfg = load_config(Path::new("TW/config.yaml"), Path::new("cookies.json"))?;
@@ -448,21 +1082,116 @@ async fn get_live_status(
             } else {
                 &cfg.twitch.channel_id
             };
-            let mut channel_name = get_channel_name("TW", channel_id).unwrap();
-            if channel_name.is_none() {
-                channel_name = Some(channel_id.to_string());
+            let channel_name = get_channel_name("TW", channel_id)
+                .unwrap()
+                .unwrap_or_else(|| channel_id.to_string());
+            let mut twitch = SourceStatusReport::offline(channel_name, channel_id);
+            if get_twitch_live_status(channel_id).await? {
+                twitch.status = "live";
             }
+            report.twitch = Some(twitch);
+        }
+        _ => return Err(format!("不支持的平台: {}", platform).into()),
+    }
+    Ok(report)
+}
 
-            if get_twitch_live_status(channel_id).await? {
-                println!("{} 在 Twitch 直播中", channel_name.unwrap());
-            } else {
-                println!("{} 未在 Twitch 直播", channel_name.unwrap());
+/// Prints the report the way `get-live-status` always has.
+fn print_live_status(report: &LiveStatusReport) {
+    if let Some(bili) = &report.bilibili {
+        if bili.is_live {
+            println!(
+                "B站直播状态: 直播中, 标题: {}, 分区: {} （ID: {}）",
+                bili.title.as_deref().unwrap_or(""),
+                bili.area_name.as_deref().unwrap_or("未知"),
+                bili.area_id.unwrap_or(0)
+            );
+        } else {
+            println!("B站直播状态: 未直播");
+        }
+    }
+    if let Some(youtube) = &report.youtube {
+        for restriction in &youtube.restrictions {
+            println!("频道 {} 正在进行{}", youtube.channel_name, restriction);
+        }
+        match (youtube.status, &youtube.title) {
+            ("upcoming", title) => {
+                let start_time = youtube.scheduled_start.map(|t| t.to_string());
+                match title {
+                    Some(title) => println!(
+                        "{} 计划于 {} 开始 YouTube 直播, 标题: {}",
+                        youtube.channel_name,
+                        start_time.unwrap_or_default(),
+                        title
+                    ),
+                    None => println!(
+                        "{} 计划于 {} 开始 YouTube 直播",
+                        youtube.channel_name,
+                        start_time.unwrap_or_default()
+                    ),
+                }
             }
+            ("live", title) => println!(
+                "{} 在 YouTube 直播中, 标题: {}",
+                youtube.channel_name,
+                title.as_deref().unwrap_or("")
+            ),
+            // A simulcast found live on Twitch is printed below
+            _ if report.twitch.is_some() => {}
+            _ => println!("{} 未直播", youtube.channel_name),
         }
-        _ => {
-            println!("不支持的平台: {}", platform);
+    }
+    if let Some(twitch) = &report.twitch {
+        match (twitch.status, &twitch.title) {
+            ("live", Some(title)) => {
+                println!("{} 在 Twitch 直播中, 标题: {}", twitch.channel_name, title)
+            }
+            ("live", None) => println!("{} 在 Twitch 直播中", twitch.channel_name),
+            _ => println!("{} 未在 Twitch 直播", twitch.channel_name),
         }
     }
+}
+
+/// Copies a biliup cookie file to cookies.json after checking that it is logged in.
+/// The previous cookies.json is kept as cookies.json.bak.
+async fn import_cookies(file: &str) -> Result<(), Box<dyn Error>> {
+    let credentials = load_credentials(file)?;
+    let uname = bili_check_login(&credentials)
+        .await
+        .map_err(|e| format!("{} 无法登录: {}", file, e))?;
+    if Path::new("cookies.json").exists() {
+        if fs::canonicalize("cookies.json")? == fs::canonicalize(file)? {
+            println!("cookies.json 中 {} 的登录信息有效", uname);
+            return Ok(());
+        }
+        fs::copy("cookies.json", "cookies.json.bak")?;
+    }
+    fs::copy(file, "cookies.json")?;
+    println!("已导入 {} 的登录信息到 cookies.json", uname);
+    Ok(())
+}
+
+/// Fails when the channel does not exist, so that scripts can ask for it again.
+async fn check_channel(platform: &str, channel_id: &str) -> Result<(), Box<dyn Error>> {
+    let (channel_id, exists) = match platform {
+        "YT" => {
+            let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
+            let channel_id = resolve_youtube_channel(channel_id, cfg.proxy.as_deref())?;
+            let exists = youtube_channel_exists(&channel_id, cfg.proxy.as_deref()).await?;
+            (channel_id, exists)
+        }
+        "TW" => {
+            let client = ClientBuilder::new(reqwest::Client::new()).build();
+            let channel_id = resolve_twitch_channel(channel_id);
+            let exists = twitch_channel_exists(&channel_id, client).await?;
+            (channel_id, exists)
+        }
+        _ => return Err(format!("不支持的平台: {}", platform).into()),
+    };
+    if !exists {
+        return Err(format!("{} 频道 {} 不存在", platform, channel_id).into());
+    }
+    println!("{} 频道 {} 存在", platform, channel_id);
     Ok(())
 }
 
@@ -500,7 +1229,7 @@ async fn get_live_title(
             let client = ClientBuilder::new(reqwest::Client::new()).build();
 
             let title = get_twitch_live_title(channel_id, client).await?;
-            if title != "" {
+            if !title.is_empty() {
                 // println!("Twitch直播标题: {}", title);
                 tracing::info!("Twitch 直播标题: {}", title);
             }
@@ -512,15 +1241,169 @@ async fn get_live_title(
         }
     }
 }
+/// `<name>.pid` in the working directory, held while a config is being run so a second
+/// instance for the same config refuses to start. Removed again when dropped.
+struct PidFile {
+    path: String,
+}
+
+impl PidFile {
+    /// How long an empty pid file counts as being written by an instance starting now.
+    const WRITE_GRACE: Duration = Duration::from_secs(5);
+
+    /// Creates `<name>.pid` exclusively, so of two instances starting together only one
+    /// wins. A file left behind by a process that is gone is replaced.
+    fn acquire(name: &str) -> Result<PidFile, Box<dyn Error>> {
+        let path = format!("{}.pid", name);
+        for _ in 0..3 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    io::Write::write_all(&mut file, std::process::id().to_string().as_bytes())?;
+                    return Ok(PidFile { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            let pid = fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| content.trim().parse::<u32>().ok());
+            let held = match pid {
+                Some(pid) => pid != std::process::id() && process_alive(pid),
+                None => fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| {
+                        modified.elapsed().is_ok_and(|age| age < Self::WRITE_GRACE)
+                    }),
+            };
+            if held {
+                return Err(format!(
+                    "{} 已在运行 (pid {})，如确需多开请加 --allow-multiple",
+                    name,
+                    pid.map_or("未知".to_string(), |pid| pid.to_string())
+                )
+                .into());
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(format!("无法创建 {}，请稍后重试", path).into())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether `pid` is running. EPERM means it exists but belongs to another user.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists, nothing is sent
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    StdCommand::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+/// Resolves on Ctrl-C, and on SIGTERM (`docker stop`, systemd) or closing the console
+/// window on Windows.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let mut terminate =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok();
+    #[cfg(windows)]
+    let mut terminate = tokio::signal::windows::ctrl_close().ok();
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(terminate) => {
+                terminate.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminated => {}
+    }
+}
+
+/// Cleans up after `run_bilistream` was interrupted by [`shutdown_signal`]: stops the
+/// ffmpeg and danmaku-cli started by this process, closes the history record and
+/// stops the Bilibili live with `StopLiveOnExit`.
+async fn shutdown(config_path: &str) {
+    tracing::info!("收到退出信号，正在停止转播");
+    let streaming = ffmpeg::stop_ffmpeg();
+    if streaming {
+        // The ffmpeg thread removes the lock file once ffmpeg has exited
+        for _ in 0..50 {
+            if !ffmpeg::is_any_ffmpeg_running() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    let cfg = match load_config(Path::new(config_path), Path::new("cookies.json")) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            tracing::error!("读取配置失败，跳过停播: {}", e);
+            return;
+        }
+    };
+    let platform = if cfg.platform == "Twitch" { "TW" } else { "YT" };
+    stop_danmaku(platform);
+    if streaming {
+        if let Err(e) = record_live_end(platform, LIVE_RESTARTS.load(Ordering::Relaxed)) {
+            tracing::error!("写入转播记录失败: {}", e);
+        }
+        if cfg.bililive.stop_live_on_exit {
+            match bili_stop_live(&cfg).await {
+                Ok(()) => tracing::info!("B站直播已停止"),
+                Err(e) => tracing::error!("停止B站直播失败: {}", e),
+            }
+        }
+    }
+    tracing::info!("bilistream 已退出");
+}
+
 async fn start_live(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
     bili_start_live(&cfg).await?;
+    clear_manual_stop();
     println!("直播开始成功");
     Ok(())
 }
 
 async fn stop_live(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
+    // Flag first so the streaming process does not restart the killed ffmpeg
+    let platform = if cfg.platform == "Twitch" { "TW" } else { "YT" };
+    let streaming = if ffmpeg::is_any_ffmpeg_running() {
+        ["YT", "TW"]
+            .into_iter()
+            .find(|p| Path::new(&format!("ffmpeg.lock-{}", p)).exists())
+            .unwrap_or(platform)
+    } else {
+        platform
+    };
+    set_manual_stop(streaming)?;
+    ffmpeg::stop_ffmpeg_process();
     bili_stop_live(&cfg).await?;
     println!("直播停止成功");
     Ok(())
@@ -541,48 +1424,99 @@ async fn change_live_title(
     Ok(())
 }
 
-fn monitor_lol_game(puuid: Option<String>) -> Result<(), Box<dyn Error>> {
-    if let Some(puuid_str) = puuid {
-        let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
-        let interval = cfg.lol_monitor_interval.unwrap_or(1);
-        thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            loop {
-                rt.block_on(async {
-                    let output = StdCommand::new("python3")
-                        .arg("get_lol_id.py")
-                        .arg(cfg.riot_api_key.clone().unwrap())
-                        .arg(&puuid_str)
-                        .output()
-                        .unwrap();
-                    if let Ok(ids) = String::from_utf8(output.stdout) {
-                        // tracing::info!("In game players: {}", ids.trim());
-                        if let Ok(invalid_words) = fs::read_to_string("invalid_words.txt") {
-                            if let Some(word) =
-                                invalid_words.lines().find(|word| ids.contains(word))
-                            {
-                                bili_stop_live(&cfg).await.unwrap();
-                                tracing::info!("检测到非法词汇:{}，停止直播", word);
-                                return;
-                            }
-                        }
+async fn test_danmaku(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = load_config(Path::new(config_path), Path::new("cookies.json"))?;
+    match bili_check_danmaku(&cfg).await {
+        Ok(addr) => println!("弹幕连接测试成功，服务器: {}", addr),
+        Err(e) => println!("弹幕连接测试失败: {}", e),
+    }
+    Ok(())
+}
+
+/// Keeps the first character of the game name and hides the rest and the tag.
+fn mask_riot_id(id: &str) -> String {
+    match id.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => String::new(),
+    }
+}
+
+/// Starts the game monitor for the current area, see [`select_game_monitor`].
+fn start_game_monitor(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    if !GAME_MONITOR_AREAS.contains(&cfg.bililive.area_v2) {
+        return Ok(());
+    }
+    let player = get_puuid_from_file(&cfg.youtube.channel_name)?;
+    if let Some(monitor) = select_game_monitor(cfg, player) {
+        monitor_game(monitor)?;
+    }
+    Ok(())
+}
+
+/// Polls the game of the streamer while ffmpeg runs: stops the live when a player
+/// name contains a word of invalid_words.txt and optionally lists the players. The
+/// thread ends once the live was stopped.
+fn monitor_game(monitor: Box<dyn GameMonitor>) -> Result<(), Box<dyn Error>> {
+    let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
+    let interval = monitor.poll_interval(cfg.lol_monitor_interval.unwrap_or(1));
+    thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut last_ids = Vec::new();
+        loop {
+            let stopped = rt.block_on(async {
+                let ids = match monitor.player_ids().await {
+                    Ok(ids) => ids,
+                    Err(e) => {
+                        tracing::debug!("获取对局玩家失败: {}", e);
+                        return false;
+                    }
+                };
+                // tracing::info!("In game players: {:?}", ids);
+                if cfg.show_lol_participants && !ids.is_empty() && ids != last_ids {
+                    last_ids = ids.clone();
+                    let players = ids.iter().enumerate().map(|(i, id)| {
+                        let id = if cfg.mask_lol_participants {
+                            mask_riot_id(id)
+                        } else {
+                            id.clone()
+                        };
+                        format!("{}. {}", i + 1, id)
+                    });
+                    let mut danmaku = vec!["本局玩家:".to_string()];
+                    danmaku.extend(players);
+                    bili_send_danmaku_list(&cfg, &danmaku, "").await;
+                }
+                let Some(word) = find_invalid_word(&ids) else {
+                    return false;
+                };
+                match bili_stop_live(&cfg).await {
+                    Ok(()) => {
+                        tracing::info!("检测到非法词汇:{}，停止直播", word);
+                        true
+                    }
+                    Err(e) => {
+                        tracing::error!("检测到非法词汇:{}，停止直播失败: {}", word, e);
+                        false
                     }
-                });
-                // if ffmpeg is not running, stop the thread
-                if !ffmpeg::is_any_ffmpeg_running() {
-                    return;
                 }
-                thread::sleep(Duration::from_secs(interval));
+            });
+            // if the live was stopped or ffmpeg is not running, stop the thread
+            if stopped || !ffmpeg::is_any_ffmpeg_running() {
+                return;
             }
-        });
-    }
+            thread::sleep(Duration::from_secs(interval));
+        }
+    });
     Ok(())
 }
 
-fn get_puuid_from_file(channel_name: &str) -> Result<Option<String>, Box<dyn Error>> {
+/// Finds the puuid of `channel_name` in puuid.txt, where a line looks like
+/// `(Kamito) [puuid]`, optionally followed by the region, e.g. `(Name) [puuid] kr`.
+fn get_puuid_from_file(channel_name: &str) -> Result<Option<RiotPlayer>, Box<dyn Error>> {
     let file = fs::File::open("./puuid.txt")?;
     let reader = io::BufReader::new(file);
     let mut puuid = None;
+    let re = Regex::new(r"\[(.*?)\]\s*(\w+)?").unwrap();
 
     for line in reader.lines() {
         let line = line?;
@@ -590,9 +1524,9 @@ fn get_puuid_from_file(channel_name: &str) -> Result<Option<String>, Box<dyn Err
             .to_lowercase()
             .contains(&format!("({})", channel_name).to_lowercase())
         {
-            let re = Regex::new(r"\[(.*?)\]").unwrap();
             if let Some(captures) = re.captures(&line) {
-                puuid = captures.get(1).map(|m| m.as_str().to_string());
+                let region = captures.get(2).map(|m| m.as_str().to_string());
+                puuid = captures.get(1).map(|m| (m.as_str().to_string(), region));
             }
         }
     }
@@ -610,6 +1544,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("设置自定义配置文件")
                 .global(true),
         )
+        .arg(
+            Arg::new("allow-multiple")
+                .long("allow-multiple")
+                .action(clap::ArgAction::SetTrue)
+                .help("允许同一配置同时运行多个实例"),
+        )
         .arg(
             Arg::new("ffmpeg-log-level")
                 .long("ffmpeg-log-level")
@@ -626,7 +1566,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .help("检查的平台 (YT, TW, bilibili)"),
                 )
-                .arg(Arg::new("channel_id").required(false).help("检查的频道ID")),
+                .arg(Arg::new("channel_id").required(false).help("检查的频道ID"))
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("以JSON输出"),
+                ),
         )
         .subcommand(Command::new("start-live").about("开始直播"))
         .subcommand(Command::new("stop-live").about("停止直播"))
@@ -655,7 +1601,91 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 )
                 .arg(Arg::new("channel_id").required(false).help("获取的频道ID")),
         )
+        .subcommand(
+            Command::new("check-channel")
+                .about("检查频道ID是否存在")
+                .arg(
+                    Arg::new("platform")
+                        .required(true)
+                        .help("频道所在平台 (YT, TW)"),
+                )
+                .arg(
+                    Arg::new("channel_id")
+                        .required(true)
+                        .help("要检查的频道ID，YT也可填@handle、频道或视频链接，TW也可填频道链接"),
+                ),
+        )
+        .subcommand(
+            Command::new("switch-channel")
+                .about("切换监听的频道，主循环下次检测时生效")
+                .arg(
+                    Arg::new("platform")
+                        .required(true)
+                        .value_parser(["YT", "TW"])
+                        .help("频道所在平台 (YT, TW)"),
+                )
+                .arg(
+                    Arg::new("channel")
+                        .required(true)
+                        .help("频道列表中的频道名或频道ID"),
+                )
+                .arg(
+                    Arg::new("area")
+                        .required(false)
+                        .help("分区名称，不填则沿用当前分区"),
+                ),
+        )
         .subcommand(Command::new("login").about("登录"))
+        .subcommand(
+            Command::new("import-cookies")
+                .about("导入biliup的cookies.json，免去重新扫码")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("biliup登录生成的cookie文件"),
+                ),
+        )
+        .subcommand(Command::new("test-danmaku").about("测试弹幕连接与凭证"))
+        .subcommand(
+            Command::new("export-stats").about("导出转播记录为CSV").arg(
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .value_name("FILE")
+                    .help("输出文件，不填则打印到终端"),
+            ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("以JSON输出最近的转播记录和本周转播时长")
+                .arg(
+                    Arg::new("count")
+                        .short('n')
+                        .long("count")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                        .help("输出的记录条数"),
+                ),
+        )
+        .subcommand(
+            Command::new("search-area")
+                .about("按名称模糊搜索B站分区ID")
+                .arg(
+                    Arg::new("query")
+                        .required(true)
+                        .help("分区名称的一部分或分区ID"),
+                ),
+        )
+        .subcommand(Command::new("doctor").about("检测显卡与ffmpeg硬件编码器"))
+        .subcommand(
+            Command::new("diagnose").about("生成脱敏的运行诊断包").arg(
+                Arg::new("log")
+                    .long("log")
+                    .value_name("FILE")
+                    .help("附带的日志文件（如重定向保存的 bilistream 输出）"),
+            ),
+        )
         .get_matches();
 
     let config_path = matches
@@ -673,10 +1703,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(("get-live-status", sub_m)) => {
             let platform = sub_m.get_one::<String>("platform").unwrap();
             let channel_id = sub_m.get_one::<String>("channel_id");
-            if channel_id.is_none() {
-                get_live_status(platform, None).await?;
+            let report = get_live_status(platform, channel_id.map(String::as_str)).await?;
+            if sub_m.get_flag("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
             } else {
-                get_live_status(platform, Some(channel_id.unwrap())).await?;
+                print_live_status(&report);
             }
         }
         Some(("start-live", _)) => {
@@ -692,29 +1723,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(("get-live-title", sub_m)) => {
             let platform = sub_m.get_one::<String>("platform").unwrap();
             let channel_id = sub_m.get_one::<String>("channel_id");
-            if channel_id.is_none() {
-                // tracing::info!("直播标题: {}", get_live_title(platform, None).await?);
-                println!("直播标题: {}", get_live_title(platform, None).await?);
-            } else {
-                // tracing::info!("直播标题: {}", get_live_title(platform, Some(channel_id.unwrap())).await?);
-                println!(
-                    "直播标题: {}",
-                    get_live_title(platform, Some(channel_id.unwrap())).await?
-                );
-            }
+            println!(
+                "直播标题: {}",
+                get_live_title(platform, channel_id.map(String::as_str)).await?
+            );
         }
         Some(("get-live-topic", sub_m)) => {
             let platform = sub_m.get_one::<String>("platform").unwrap();
             let channel_id = sub_m.get_one::<String>("channel_id");
-            if channel_id.is_none() {
-                println!("YouTube直播分区: {}", get_live_topic(platform, None).await?);
-            } else {
-                println!(
-                    "YouTube直播分区: {}",
-                    get_live_topic(platform, Some(channel_id.unwrap())).await?
-                );
+            println!(
+                "YouTube直播分区: {}",
+                get_live_topic(platform, channel_id.map(String::as_str)).await?
+            );
+        }
+        Some(("import-cookies", sub_m)) => {
+            let file = sub_m.get_one::<String>("file").unwrap();
+            import_cookies(file).await?;
+        }
+        Some(("check-channel", sub_m)) => {
+            let platform = sub_m.get_one::<String>("platform").unwrap();
+            let channel_id = sub_m.get_one::<String>("channel_id").unwrap();
+            check_channel(platform, channel_id).await?;
+        }
+        Some(("switch-channel", sub_m)) => {
+            let platform = sub_m.get_one::<String>("platform").unwrap();
+            let channel = sub_m.get_one::<String>("channel").unwrap();
+            let area = sub_m.get_one::<String>("area").map(String::as_str);
+            let (channel_name, area_id) = switch_channel(platform, channel, area)?;
+            println!(
+                "已切换到 {} 频道 {}，分区 {}",
+                platform,
+                channel_name,
+                get_area_name(area_id).unwrap_or("未知")
+            );
+        }
+        Some(("test-danmaku", _)) => {
+            test_danmaku(config_path).await?;
+        }
+        Some(("export-stats", sub_m)) => {
+            let csv = history_to_csv(&load_history());
+            match sub_m.get_one::<String>("output") {
+                Some(path) => {
+                    fs::write(path, csv)?;
+                    println!("转播记录已导出到 {}", path);
+                }
+                None => print!("{}", csv),
+            }
+        }
+        Some(("history", sub_m)) => {
+            let count = *sub_m.get_one::<usize>("count").unwrap();
+            let history = serde_json::json!({
+                "records": recent_history(count),
+                "week_seconds": weekly_duration(&load_history()).num_seconds(),
+            });
+            println!("{}", serde_json::to_string_pretty(&history)?);
+        }
+        Some(("search-area", sub_m)) => {
+            let query = sub_m.get_one::<String>("query").unwrap();
+            let areas = search_areas(query);
+            if areas.is_empty() {
+                println!("没有找到匹配 {} 的分区", query);
+            }
+            for (id, name) in areas {
+                println!("{}\t{}", id, name);
             }
         }
+        Some(("doctor", _)) => {
+            println!("{}", run_doctor());
+        }
+        Some(("diagnose", sub_m)) => {
+            let log_path = sub_m.get_one::<String>("log").map(String::as_str);
+            let archive = create_diagnose_bundle(log_path)?;
+            println!("诊断包已生成: {}", archive);
+        }
         Some(("login", _)) => {
             let mut command = StdCommand::new("./login-biliup");
             command.arg("login");
@@ -728,8 +1809,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .unwrap_or("default");
             let process_name = format!("bilistream-{}", file_name);
             set_title(&process_name);
+            let _pid_file = if matches.get_flag("allow-multiple") {
+                None
+            } else {
+                Some(PidFile::acquire(&process_name)?)
+            };
             // Default behavior: run bilistream with the provided config
-            run_bilistream(config_path, ffmpeg_log_level).await?;
+            tokio::select! {
+                result = run_bilistream(config_path, ffmpeg_log_level) => {
+                    if let Err(e) = result {
+                        tracing::error!("在「{}」阶段退出: {}", current_stage().describe(), e);
+                        return Err(e);
+                    }
+                }
+                _ = shutdown_signal() => shutdown(config_path).await,
+            }
         }
     }
     Ok(())
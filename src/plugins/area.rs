@@ -0,0 +1,113 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+/// Area names and title keywords, read from the working directory at first use.
+const AREA_RULES_FILE: &str = "area_rules.json";
+/// Rules used when `area_rules.json` is missing or invalid.
+const DEFAULT_AREA_RULES: &str = include_str!("../../area_rules.json");
+
+#[derive(Debug, Deserialize)]
+struct Area {
+    id: u64,
+    name: String,
+}
+
+/// Titles containing any of `keywords` (case-insensitive) go to `area_id`.
+#[derive(Debug, Deserialize)]
+struct AreaRule {
+    keywords: Vec<String>,
+    area_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AreaRules {
+    areas: Vec<Area>,
+    /// Checked in order, the first matching rule wins.
+    rules: Vec<AreaRule>,
+    /// Holodex `topic_id`s and their area, checked before the keyword rules.
+    #[serde(default)]
+    topics: HashMap<String, u64>,
+}
+
+static AREA_RULES: OnceLock<AreaRules> = OnceLock::new();
+
+fn area_rules() -> &'static AreaRules {
+    AREA_RULES.get_or_init(|| {
+        let rules = match fs::read_to_string(AREA_RULES_FILE) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| {
+                    tracing::error!("{} 格式错误，使用内置分区规则: {}", AREA_RULES_FILE, e)
+                })
+                .ok(),
+            Err(_) => None,
+        };
+        rules.unwrap_or_else(|| {
+            serde_json::from_str(DEFAULT_AREA_RULES).expect("内置分区规则格式错误")
+        })
+    })
+}
+
+/// determines the area id based on the live title.
+pub fn check_area_id_with_title(live_title: &str, current_area_id: u64) -> u64 {
+    let title = live_title.to_lowercase();
+    let title = title.replace("_", " ");
+
+    area_rules()
+        .rules
+        .iter()
+        .find(|rule| {
+            rule.keywords
+                .iter()
+                .any(|keyword| title.contains(&keyword.to_lowercase()))
+        })
+        .map_or(current_area_id, |rule| rule.area_id)
+}
+
+/// The area of a Holodex `topic_id` (e.g. `Apex_Legends`), compared case-insensitively.
+pub fn area_id_for_topic(topic: &str) -> Option<u64> {
+    area_rules()
+        .topics
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(topic.trim()))
+        .map(|(_, area_id)| *area_id)
+}
+
+pub fn get_area_name(area_id: u64) -> Option<&'static str> {
+    let name = area_rules()
+        .areas
+        .iter()
+        .find(|area| area.id == area_id)
+        .map(|area| area.name.as_str());
+    if name.is_none() {
+        tracing::error!("未知的分区ID: {}", area_id);
+    }
+    name
+}
+
+/// Looks an area up by its exact name, or by part of it when only one area matches.
+pub fn get_area_id(area_name: &str) -> Option<u64> {
+    let areas = &area_rules().areas;
+    if let Some(area) = areas.iter().find(|area| area.name == area_name) {
+        return Some(area.id);
+    }
+    match search_areas(area_name).as_slice() {
+        [(id, _)] => Some(*id),
+        _ => None,
+    }
+}
+
+/// Areas whose name contains `query` (case-insensitive), or whose ID equals it.
+pub fn search_areas(query: &str) -> Vec<(u64, &'static str)> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    area_rules()
+        .areas
+        .iter()
+        .filter(|area| area.name.to_lowercase().contains(&query) || area.id.to_string() == query)
+        .map(|area| (area.id, area.name.as_str()))
+        .collect()
+}
@@ -1,11 +1,58 @@
-use crate::config::Config;
+use crate::config::{renew_credentials, Config, Credentials};
 use reqwest::{cookie::Jar, Url};
-use reqwest_middleware::ClientBuilder;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::policies::ExponentialBackoff;
 use reqwest_retry::RetryTransientMiddleware;
 use serde_json::Value;
 use std::error::Error;
 use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// When the last danmaku was sent. Holding the lock while waiting queues concurrent senders.
+static LAST_DANMAKU_SENT: Mutex<Option<Instant>> = Mutex::const_new(None);
+
+/// Error for responses saying the cookie is no longer valid (code -101 or -111).
+#[derive(Debug)]
+pub struct BiliAuthError(String);
+
+impl std::fmt::Display for BiliAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "B站凭证已失效: {}", self.0)
+    }
+}
+
+impl Error for BiliAuthError {}
+
+fn check_auth(res: &Value) -> Result<(), Box<dyn Error>> {
+    if res["code"] == -101 || res["code"] == -111 {
+        return Err(Box::new(BiliAuthError(res["message"].to_string())));
+    }
+    Ok(())
+}
+
+/// Serializes renewals when several requests fail on the same expired cookie.
+static RENEW_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Runs `request`, and when it fails because the cookie expired, renews cookies.json
+/// with its refresh_token and runs it once more with the new credentials.
+async fn with_auth_retry<F, Fut>(cfg: &Config, request: F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(Config) -> Fut,
+    Fut: std::future::Future<Output = Result<(), Box<dyn Error>>>,
+{
+    match request(cfg.clone()).await {
+        Err(e) if e.is::<BiliAuthError>() => tracing::warn!("{}，尝试刷新cookies.json", e),
+        result => return result,
+    }
+    let credentials = {
+        let _guard = RENEW_LOCK.lock().await;
+        renew_credentials()?
+    };
+    let mut cfg = cfg.clone();
+    cfg.bililive.credentials = credentials;
+    request(cfg).await
+}
 
 /// Retrieves the live status of a Bilibili room.
 ///
@@ -34,7 +81,7 @@ pub async fn get_bili_live_status(room: i32) -> Result<(bool, String, u64), Box<
         .build();
     // Make the GET request to check the live status
     let res: Value = client
-        .get(&format!(
+        .get(format!(
             "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={}",
             room
         ))
@@ -62,6 +109,10 @@ pub async fn get_bili_live_status(room: i32) -> Result<(bool, String, u64), Box<
 ///
 /// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
 pub async fn bili_start_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(cfg, |cfg| async move { start_live_request(&cfg).await }).await
+}
+
+async fn start_live_request(cfg: &Config) -> Result<(), Box<dyn Error>> {
     let cookie = format!(
         "SESSDATA={};bili_jct={};DedeUserID={};DedeUserID__ckMd5={}",
         cfg.bililive.credentials.sessdata,
@@ -87,7 +138,7 @@ pub async fn bili_start_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
         .build();
 
     // Make the POST request to start the live stream
-    let _res: Value = client
+    let res: Value = client
         .post("https://api.live.bilibili.com/room/v1/Room/startLive")
         .header("Accept", "application/json, text/plain, */*")
         .header(
@@ -105,6 +156,7 @@ pub async fn bili_start_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
         .await?
         .json()
         .await?;
+    check_auth(&res)?;
     // tracing::info!("{:#?}", _res);
     // Optionally, handle the response if needed
     // println!("{:#?}", res);
@@ -122,6 +174,14 @@ pub async fn bili_start_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
 ///
 /// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
 pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(
+        cfg,
+        |cfg| async move { change_live_title_request(&cfg).await },
+    )
+    .await
+}
+
+async fn change_live_title_request(cfg: &Config) -> Result<(), Box<dyn Error>> {
     let cookie = format!(
         "SESSDATA={};bili_jct={};DedeUserID={};DedeUserID__ckMd5={}",
         cfg.bililive.credentials.sessdata,
@@ -147,7 +207,7 @@ pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>>
         .build();
 
     // Make the POST request to update the live title
-    let _res: Value = client
+    let res: Value = client
         .post("https://api.live.bilibili.com/room/v1/Room/update")
         .header("Accept", "application/json, text/plain, */*")
         .header(
@@ -157,7 +217,7 @@ pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>>
         .body(format!(
             "room_id={}&platform=pc&title={}&csrf_token={}&csrf={}",
             cfg.bililive.room,
-            cfg.bililive.title,
+            cfg.bililive.bili_title(),
             cfg.bililive.credentials.bili_jct,
             cfg.bililive.credentials.bili_jct
         ))
@@ -165,6 +225,7 @@ pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>>
         .await?
         .json()
         .await?;
+    check_auth(&res)?;
 
     // Optionally, handle the response if needed
     // println!("{:#?}", res);
@@ -172,6 +233,100 @@ pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>>
     Ok(())
 }
 
+/// Updates the live room description on Bilibili.
+///
+/// # Arguments
+///
+/// * `cfg` - Reference to the application configuration.
+/// * `description` - The new room description.
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
+pub async fn bili_change_live_description(
+    cfg: &Config,
+    description: &str,
+) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(cfg, |cfg| async move {
+        change_live_description_request(&cfg, description).await
+    })
+    .await
+}
+
+async fn change_live_description_request(
+    cfg: &Config,
+    description: &str,
+) -> Result<(), Box<dyn Error>> {
+    let client = build_bili_client(cfg)?;
+    let room = cfg.bililive.room.to_string();
+    let form = [
+        ("room_id", room.as_str()),
+        ("description", description),
+        ("csrf_token", cfg.bililive.credentials.bili_jct.as_str()),
+        ("csrf", cfg.bililive.credentials.bili_jct.as_str()),
+    ];
+
+    // Make the POST request to update the room description
+    let res: Value = client
+        .post("https://api.live.bilibili.com/room/v1/Room/update")
+        .header("Accept", "application/json, text/plain, */*")
+        .form(&form)
+        .send()
+        .await?
+        .json()
+        .await?;
+    check_auth(&res)?;
+    if res["code"] != 0 {
+        return Err(format!("更新直播间简介失败: {}", res["message"]).into());
+    }
+
+    Ok(())
+}
+
+/// Moves the live room to another area without stopping the live.
+///
+/// # Arguments
+///
+/// * `cfg` - Reference to the application configuration.
+/// * `area_id` - The new area ID.
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
+pub async fn bili_update_area(cfg: &Config, area_id: u64) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(cfg, |cfg| async move {
+        update_area_request(&cfg, area_id).await
+    })
+    .await
+}
+
+async fn update_area_request(cfg: &Config, area_id: u64) -> Result<(), Box<dyn Error>> {
+    let client = build_bili_client(cfg)?;
+    let room = cfg.bililive.room.to_string();
+    let area_id = area_id.to_string();
+    let form = [
+        ("room_id", room.as_str()),
+        ("area_id", area_id.as_str()),
+        ("csrf_token", cfg.bililive.credentials.bili_jct.as_str()),
+        ("csrf", cfg.bililive.credentials.bili_jct.as_str()),
+    ];
+
+    let res: Value = client
+        .post("https://api.live.bilibili.com/room/v1/Room/update")
+        .header("Accept", "application/json, text/plain, */*")
+        .form(&form)
+        .send()
+        .await?
+        .json()
+        .await?;
+    check_auth(&res)?;
+    if res["code"] != 0 {
+        return Err(format!("更新直播分区失败: {}", res["message"]).into());
+    }
+
+    Ok(())
+}
+
 /// Stops the Bilibili live stream.
 ///
 /// # Arguments
@@ -182,6 +337,10 @@ pub async fn bili_change_live_title(cfg: &Config) -> Result<(), Box<dyn Error>>
 ///
 /// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
 pub async fn bili_stop_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(cfg, |cfg| async move { stop_live_request(&cfg).await }).await
+}
+
+async fn stop_live_request(cfg: &Config) -> Result<(), Box<dyn Error>> {
     let cookie = format!(
         "SESSDATA={};bili_jct={};DedeUserID={};DedeUserID__ckMd5={}",
         cfg.bililive.credentials.sessdata,
@@ -207,7 +366,7 @@ pub async fn bili_stop_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
         .build();
 
     // Make the POST request to stop the live stream
-    let _res: Value = client
+    let res: Value = client
         .post("https://api.live.bilibili.com/room/v1/Room/stopLive")
         .header("Accept", "application/json, text/plain, */*")
         .header(
@@ -222,9 +381,206 @@ pub async fn bili_stop_live(cfg: &Config) -> Result<(), Box<dyn Error>> {
         .await?
         .json()
         .await?;
+    check_auth(&res)?;
     // tracing::info!("{:#?}", _res);
     // Optionally, handle the response if needed
     // println!("{:#?}", res);
 
     Ok(())
 }
+
+/// Sends a danmaku message to the configured Bilibili live room.
+///
+/// Waits until `DanmakuCooldown` seconds have passed since the previous danmaku,
+/// so callers never need to sleep between messages themselves.
+///
+/// # Arguments
+///
+/// * `cfg` - Reference to the application configuration.
+/// * `msg` - The danmaku text to send.
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn Error>>` - Returns `Ok` if successful, otherwise an error.
+pub async fn bili_send_danmaku(cfg: &Config, msg: &str) -> Result<(), Box<dyn Error>> {
+    with_auth_retry(
+        cfg,
+        |cfg| async move { send_danmaku_request(&cfg, msg).await },
+    )
+    .await
+}
+
+async fn send_danmaku_request(cfg: &Config, msg: &str) -> Result<(), Box<dyn Error>> {
+    let mut last_sent = LAST_DANMAKU_SENT.lock().await;
+    if let Some(last_sent) = *last_sent {
+        tokio::time::sleep_until(last_sent + Duration::from_secs(cfg.bililive.danmaku_cooldown))
+            .await;
+    }
+    *last_sent = Some(Instant::now());
+
+    let client = build_bili_client(cfg)?;
+    let rnd = chrono::Local::now().timestamp().to_string();
+    let room = cfg.bililive.room.to_string();
+    let form = [
+        ("bubble", "0"),
+        ("msg", msg),
+        ("color", "16777215"),
+        ("mode", "1"),
+        ("fontsize", "25"),
+        ("rnd", rnd.as_str()),
+        ("roomid", room.as_str()),
+        ("csrf", cfg.bililive.credentials.bili_jct.as_str()),
+        ("csrf_token", cfg.bililive.credentials.bili_jct.as_str()),
+    ];
+
+    // Make the POST request to send the danmaku
+    let res: Value = client
+        .post("https://api.live.bilibili.com/msg/send")
+        .header("Accept", "application/json, text/plain, */*")
+        .form(&form)
+        .send()
+        .await?
+        .json()
+        .await?;
+    check_auth(&res)?;
+    if res["code"] != 0 {
+        return Err(format!("发送弹幕失败: {}", res["message"]).into());
+    }
+
+    Ok(())
+}
+
+/// Sends a list of templated danmaku messages one after another.
+///
+/// `{channel}` and `{platform}` in each template are replaced with the
+/// rebroadcast channel name and source platform. Failures are logged and
+/// do not stop the remaining messages.
+pub async fn bili_send_danmaku_list(cfg: &Config, templates: &[String], channel_name: &str) {
+    for template in templates {
+        let msg = template
+            .replace("{channel}", channel_name)
+            .replace("{platform}", &cfg.platform);
+        if let Err(e) = bili_send_danmaku(cfg, &msg).await {
+            tracing::error!("发送弹幕 {} 失败: {}", msg, e);
+        }
+    }
+}
+
+/// Builds an HTTP client carrying the Bilibili login cookies for both the
+/// `api.bilibili.com` and `api.live.bilibili.com` hosts.
+fn build_bili_client(cfg: &Config) -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    build_bili_client_with(&cfg.bililive.credentials)
+}
+
+fn build_bili_client_with(
+    credentials: &Credentials,
+) -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    let cookie = format!(
+        "SESSDATA={};bili_jct={};DedeUserID={};DedeUserID__ckMd5={}",
+        credentials.sessdata,
+        credentials.bili_jct,
+        credentials.dede_user_id,
+        credentials.dede_user_id_ckmd5
+    );
+    let jar = Jar::default();
+    for url in [
+        "https://api.bilibili.com/",
+        "https://api.live.bilibili.com/",
+    ] {
+        for pair in cookie.split(';') {
+            jar.add_cookie_str(pair, &Url::parse(url)?);
+        }
+    }
+
+    // Define the retry policy
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
+
+    // Build the HTTP client with retry middleware
+    let raw_client = reqwest::Client::builder()
+        .cookie_store(true)
+        .cookie_provider(jar.into())
+        .timeout(Duration::new(30, 0))
+        .build()?;
+    Ok(ClientBuilder::new(raw_client)
+        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .build())
+}
+
+/// Checks that the credentials are logged in and returns the account name.
+pub async fn bili_check_login(credentials: &Credentials) -> Result<String, Box<dyn Error>> {
+    let nav: Value = build_bili_client_with(credentials)?
+        .get("https://api.bilibili.com/x/web-interface/nav")
+        .send()
+        .await
+        .map_err(|e| format!("网络错误，请检查网络或代理: {}", e))?
+        .json()
+        .await?;
+    if nav["data"]["isLogin"] != true {
+        return Err("cookie 无效或已过期".into());
+    }
+    Ok(nav["data"]["uname"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Checks that the danmaku connection can be established with the current credentials.
+///
+/// Verifies the login cookies, fetches the danmaku server list of the room and
+/// opens one TCP connection to the first server.
+///
+/// # Arguments
+///
+/// * `cfg` - Reference to the application configuration.
+///
+/// # Returns
+///
+/// * `Result<String, Box<dyn Error>>` - The danmaku server that was reached, otherwise an error explaining the cause.
+pub async fn bili_check_danmaku(cfg: &Config) -> Result<String, Box<dyn Error>> {
+    let client = build_bili_client(cfg)?;
+
+    let nav: Value = client
+        .get("https://api.bilibili.com/x/web-interface/nav")
+        .send()
+        .await
+        .map_err(|e| format!("网络错误，请检查网络或代理: {}", e))?
+        .json()
+        .await?;
+    if nav["data"]["isLogin"] != true {
+        return Err("cookie 无效或已过期，请重新登录: ./bilistream login".into());
+    }
+
+    let info: Value = client
+        .get(format!(
+            "https://api.live.bilibili.com/xlive/web-room/v1/index/getDanmuInfo?id={}",
+            cfg.bililive.room
+        ))
+        .send()
+        .await
+        .map_err(|e| format!("网络错误，请检查网络或代理: {}", e))?
+        .json()
+        .await?;
+    if info["code"] != 0 {
+        return Err(format!(
+            "获取弹幕服务器失败，请检查房间号 {}: {}",
+            cfg.bililive.room, info["message"]
+        )
+        .into());
+    }
+
+    let host = &info["data"]["host_list"][0];
+    let addr = format!(
+        "{}:{}",
+        host["host"].as_str().ok_or("弹幕服务器列表为空")?,
+        host["port"].as_u64().unwrap_or(2243)
+    );
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio::net::TcpStream::connect(&addr),
+    )
+    .await
+    .map_err(|_| format!("连接弹幕服务器 {} 超时", addr))?
+    .map_err(|e| format!("连接弹幕服务器 {} 失败: {}", addr, e))?;
+
+    Ok(addr)
+}
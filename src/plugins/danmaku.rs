@@ -1,15 +1,22 @@
 use crate::config::load_config;
 use crate::config::Config;
 use crate::plugins::ffmpeg;
+use crate::plugins::{
+    bili_send_danmaku, bili_send_danmaku_list, check_area_id_with_title, find_banned_keyword,
+    get_area_id, get_area_name, get_bili_live_status, load_history,
+};
 use regex::Regex;
 use serde_json::Value;
 use serde_yaml;
-use std::process::{Command, Stdio};
+use std::error::Error;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{
     fs,
-    io::{self, BufRead},
+    io::{self, BufRead, Write},
     path::Path,
 };
 /// Checks if any danmaku lock file exists.
@@ -56,6 +63,7 @@ pub fn get_channel_id(
     let file = fs::File::open(&file_path)?;
     let reader = io::BufReader::new(file);
     // tracing::info!("检查频道: {}", file_path);
+    let re = Regex::new(r"\[(.*?)\]").unwrap();
     for line in reader.lines() {
         let line = line?;
         if line
@@ -63,11 +71,8 @@ pub fn get_channel_id(
             .contains(&format!("({})", channel_name).to_lowercase())
         {
             // Extract channel name using regex
-            let re = Regex::new(r"\[(.*?)\]").unwrap();
             if let Some(captures) = re.captures(&line) {
-                return Ok(captures
-                    .get(1)
-                    .map_or(None, |m| Some(m.as_str().to_string())));
+                return Ok(captures.get(1).map(|m| m.as_str().to_string()));
             }
         }
     }
@@ -81,26 +86,24 @@ pub fn get_channel_name(
     let file_path = format!("./{}/{}_channels.txt", platform, platform);
     let file = fs::File::open(&file_path)?;
     let reader = io::BufReader::new(file);
+    let re = Regex::new(r"\((.*?)\)").unwrap();
     for line in reader.lines() {
         let line = line?;
-        if line.contains(channel_id) {
-            let re = Regex::new(r"\((.*?)\)").unwrap();
-            if re.is_match(&line) {
-                return Ok(Some(
-                    re.captures(&line)
-                        .unwrap()
-                        .get(1)
-                        .unwrap()
-                        .as_str()
-                        .to_string(),
-                ));
-            }
+        if line.contains(channel_id) && re.is_match(&line) {
+            return Ok(Some(
+                re.captures(&line)
+                    .unwrap()
+                    .get(1)
+                    .unwrap()
+                    .as_str()
+                    .to_string(),
+            ));
         }
     }
     Ok(None)
 }
 
-/// Checks live status using the bilistream CLI.
+// Checks live status using the bilistream CLI.
 // async fn check_live_status(platform: &str, channel_id: &str) -> io::Result<String> {
 //     let output = Command::new("./bilistream")
 //         .arg("get-live-status")
@@ -143,8 +146,7 @@ fn update_config(
     config.bililive.area_v2 = area_id;
 
     // Serialize Config struct back to YAML
-    let updated_yaml =
-        serde_yaml::to_string(&config).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let updated_yaml = serde_yaml::to_string(&config).map_err(io::Error::other)?;
 
     // Write the updated YAML back to config.yaml
     fs::write(config_path, updated_yaml)?;
@@ -153,70 +155,294 @@ fn update_config(
     Ok(())
 }
 
-/// determines the area id based on the live title.
-pub fn check_area_id_with_title(live_title: &str, current_area_id: u64) -> u64 {
-    let title = live_title.to_lowercase();
-    let title = title.replace("_", " ");
-
-    if title.contains("valorant") || title.contains("ヴァロ") {
-        329
-    } else if title.contains("league of legends")
-        || title.contains("lol")
-        || title.contains("ろる")
-        || title.contains("k4sen")
-    {
-        86
-    } else if title.contains("minecraft") || title.contains("マイクラ") {
-        216
-    } else if title.contains("overwatch") {
-        87
-    } else if title.contains("deadlock") {
-        927
-    } else if title.contains("final fantasy online")
-        || title.contains("漆黒メインクエ")
-        || title.contains("ff14")
-    {
-        102
-    } else if title.contains("apex") {
-        240
-    } else if title.contains("スト６") || title.contains("street fighter") {
-        433
-    } else if title.contains("yu-gi-oh") || title.contains("遊戯王") {
-        407
-    } else if title.contains("splatoon") || title.contains("スプラトゥーン3") {
-        694
-    } else if title.contains("原神") {
-        321
-    } else if title.contains("pokemon")
-        || title.contains("core keeper")
-        || title.contains("terraria")
-        || title.contains("tgc card shop simulator")
-        || title.contains("stardew valley")
-        || title.contains("gta")
-    {
-        235
-    } else if title.contains("tarkov") || title.contains("タルコフ") {
-        252
-    } else if title.contains("call of duty") || title.contains("BO6") {
-        318
+/// How many past rebroadcasts `%历史` replies with.
+const HISTORY_REPLY_COUNT: usize = 3;
+/// Minimum time between two `%历史` replies.
+const HISTORY_REPLY_COOLDOWN: Duration = Duration::from_secs(60);
+
+static LAST_HISTORY_REPLY: Mutex<Option<Instant>> = Mutex::new(None);
+/// Last `%状态` reply, sharing the cooldown length of `%历史`.
+static LAST_STATUS_REPLY: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Returns false when `last` was within [`HISTORY_REPLY_COOLDOWN`], otherwise restarts it.
+fn reply_cooldown_passed(last: &Mutex<Option<Instant>>) -> bool {
+    let mut last = last.lock().unwrap_or_else(|e| e.into_inner());
+    if last.is_some_and(|last| last.elapsed() < HISTORY_REPLY_COOLDOWN) {
+        return false;
+    }
+    *last = Some(Instant::now());
+    true
+}
+
+/// When a `%转播%` command last switched the channel, see `BiliLive.DanmakuCommandCooldown`.
+static LAST_SWITCH_COMMAND: Mutex<Option<Instant>> = Mutex::new(None);
+/// When the viewers were last told to wait, so a burst of commands gets a single reply.
+static LAST_COOLDOWN_REPLY: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Returns true when `last` is set and within `window`.
+fn within(last: &Mutex<Option<Instant>>, window: Duration) -> bool {
+    last.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some_and(|last| last.elapsed() < window)
+}
+
+/// Accepts a `%转播%` command unless a switch succeeded within the cooldown of
+/// `{platform}/config.yaml`. The viewers are then told to wait, at most once per cooldown.
+async fn switch_command_allowed(platform: &str) -> bool {
+    let config_path = format!("{}/config.yaml", platform);
+    let cfg = match load_config(Path::new(&config_path), Path::new("cookies.json")) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            tracing::error!("加载配置失败: {}", e);
+            return false;
+        }
+    };
+    let cooldown = Duration::from_secs(cfg.bililive.danmaku_command_cooldown);
+    if !within(&LAST_SWITCH_COMMAND, cooldown) {
+        return true;
+    }
+    tracing::info!("换台指令冷却中，忽略");
+    if !within(&LAST_COOLDOWN_REPLY, cooldown) {
+        *LAST_COOLDOWN_REPLY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        if let Err(e) = bili_send_danmaku(&cfg, "换台太频繁，请稍后").await {
+            tracing::error!("{}", e);
+        }
+    }
+    false
+}
+
+/// Replies to `%历史` with the latest rebroadcasts, one danmaku per record.
+async fn reply_history() {
+    if !reply_cooldown_passed(&LAST_HISTORY_REPLY) {
+        return;
+    }
+    let cfg = match load_config(Path::new("YT/config.yaml"), Path::new("cookies.json")) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            tracing::error!("加载配置失败: {}", e);
+            return;
+        }
+    };
+    let history = load_history();
+    let replies: Vec<String> = if history.is_empty() {
+        vec!["暂无转播记录".to_string()]
     } else {
-        current_area_id
+        history
+            .iter()
+            .rev()
+            .take(HISTORY_REPLY_COUNT)
+            .map(|record| {
+                format!(
+                    "{} {} {}",
+                    record.start.format("%m-%d %H:%M"),
+                    record.platform,
+                    record.channel_name
+                )
+            })
+            .collect()
+    };
+    bili_send_danmaku_list(&cfg, &replies, "").await;
+}
+
+/// Replies to `%状态` with whether Bilibili is live, the channel being rebroadcast
+/// and how smooth the push is, so viewers can tell if a stutter comes from here.
+async fn reply_status() {
+    if !reply_cooldown_passed(&LAST_STATUS_REPLY) {
+        return;
     }
+    let streaming = ["YT", "TW"]
+        .into_iter()
+        .find(|platform| Path::new(&format!("ffmpeg.lock-{}", platform)).exists());
+    let config_path = format!("{}/config.yaml", streaming.unwrap_or("YT"));
+    let cfg = match load_config(Path::new(&config_path), Path::new("cookies.json")) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            tracing::error!("加载配置失败: {}", e);
+            return;
+        }
+    };
+    let bili_live = match get_bili_live_status(cfg.bililive.room).await {
+        Ok((is_live, _, _)) => {
+            if is_live {
+                "B站直播中"
+            } else {
+                "B站未直播"
+            }
+        }
+        Err(_) => "B站状态未知",
+    };
+    let mut replies = vec![bili_live.to_string()];
+    match streaming {
+        Some(platform) => {
+            let channel_name = match platform {
+                "TW" => &cfg.twitch.channel_name,
+                _ => &cfg.youtube.channel_name,
+            };
+            replies.push(format!("正在转播 {} ({})", channel_name, platform));
+            match ffmpeg::streaming_speed(platform) {
+                Some(speed) if speed > 0.0 => replies.push(format!(
+                    "推流{} ({:.2}x)",
+                    ffmpeg::describe_speed(speed),
+                    speed
+                )),
+                _ => replies.push("推流连接中".to_string()),
+            }
+        }
+        None => replies.push("当前没有转播".to_string()),
+    }
+    bili_send_danmaku_list(&cfg, &replies, "").await;
 }
 
-/// Processes a single danmaku command.
-async fn process_danmaku(command: &str) {
-    // only line start with : is danmaku
-    if command.contains("WARN  [init] Connection closed by server") {
+/// Each kind of unrecognized live-danmaku-cli line is logged at most once per this.
+const UNKNOWN_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When each kind of unrecognized line was last sampled.
+static UNKNOWN_SAMPLES: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+
+/// The `[tag]` of a live-danmaku-cli line, or its first word when there is none.
+fn line_kind(line: &str) -> String {
+    if let (Some(start), Some(end)) = (line.find('['), line.find(']')) {
+        if start < end {
+            return line[start..=end].to_string();
+        }
+    }
+    line.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Logs an unrecognized line from live-danmaku-cli, limited per kind so the log is not
+/// flooded, and appends it to `BiliLive.DanmakuSampleFile` for supporting it later.
+fn sample_unknown_line(line: &str, sample_file: Option<&str>) {
+    let kind = line_kind(line);
+    if kind.is_empty() {
+        return;
+    }
+    {
+        let mut samples = UNKNOWN_SAMPLES.lock().unwrap_or_else(|e| e.into_inner());
+        match samples.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, last)) if last.elapsed() < UNKNOWN_SAMPLE_INTERVAL => return,
+            Some((_, last)) => *last = Instant::now(),
+            None => samples.push((kind.clone(), Instant::now())),
+        }
+    }
+    tracing::warn!("未识别的弹幕消息({}): {}", kind, line);
+    if let Some(path) = sample_file {
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                writeln!(
+                    file,
+                    "{} {}",
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    line
+                )
+            });
+        if let Err(e) = result {
+            tracing::error!("写入{}失败: {}", path, e);
+        }
+    }
+}
+
+/// Restarts of live-danmaku-cli in a row after which the user is told to check the cookie.
+const DANMAKU_FAILURE_ALERT: u32 = 5;
+
+static DANMAKU_CONNECTED: AtomicBool = AtomicBool::new(false);
+static DANMAKU_RECONNECTS: AtomicU32 = AtomicU32::new(0);
+/// Set when the server closed the connection at login, restarting will not help.
+static DANMAKU_COOKIE_EXPIRED: AtomicBool = AtomicBool::new(false);
+
+/// Whether live-danmaku-cli is running and has not been rejected by the server.
+pub fn danmaku_connected() -> bool {
+    DANMAKU_CONNECTED.load(Ordering::Relaxed)
+}
+
+/// How many times live-danmaku-cli was restarted in this process.
+pub fn danmaku_reconnect_count() -> u32 {
+    DANMAKU_RECONNECTS.load(Ordering::Relaxed)
+}
+
+/// Returns true for the message live-danmaku-cli prints when the cookie was rejected.
+fn check_connection_closed(line: &str) -> bool {
+    if !line.contains("WARN  [init] Connection closed by server") {
+        return false;
+    }
+    DANMAKU_CONNECTED.store(false, Ordering::Relaxed);
+    if !DANMAKU_COOKIE_EXPIRED.swap(true, Ordering::Relaxed) {
         tracing::info!("B站cookie过期，无法启动弹幕指令，请更新配置文件:./biliup login");
+    }
+    true
+}
+
+/// Starts live-danmaku-cli with threads handling its stdout and stderr.
+fn spawn_danmaku_cli(platform: &str, sample_file: Option<String>) -> io::Result<Child> {
+    let mut danmaku_cli = Command::new("./live-danmaku-cli")
+        .arg("--config")
+        .arg("config.json")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = danmaku_cli.stdout.take().expect("捕获stdout失败");
+    let stderr = danmaku_cli.stderr.take().expect("捕获stderr失败");
+
+    // Handle stdout in a separate thread
+    let stdout_sample_file = sample_file.clone();
+    let platform = platform.to_string();
+    thread::spawn(move || {
+        let reader = io::BufReader::new(stdout);
+        for line in reader.lines().map_while(Result::ok) {
+            // Process each danmaku command
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(process_danmaku(
+                    &line,
+                    &platform,
+                    stdout_sample_file.as_deref(),
+                ));
+        }
+    });
+
+    // Handle stderr in a separate thread
+    thread::spawn(move || {
+        let reader = io::BufReader::new(stderr);
+        for line in reader.lines().map_while(Result::ok) {
+            if !check_connection_closed(&line) {
+                sample_unknown_line(&line, sample_file.as_deref());
+            }
+        }
+    });
+
+    DANMAKU_CONNECTED.store(true, Ordering::Relaxed);
+    Ok(danmaku_cli)
+}
+
+/// Processes a single danmaku command read by the process of `reader_platform`.
+async fn process_danmaku(command: &str, reader_platform: &str, sample_file: Option<&str>) {
+    // only line start with : is danmaku
+    if check_connection_closed(command) {
         return;
     }
     if !command.starts_with(" :") {
+        sample_unknown_line(command, sample_file);
         return;
     }
     // tracing::info!("弹幕:{}", &command[2..]);
     let command = command.replace(" ", "");
     let normalized_danmaku = command.replace("％", "%");
+    if normalized_danmaku.trim_start_matches(':') == "%历史" {
+        reply_history().await;
+        return;
+    }
+    if normalized_danmaku.trim_start_matches(':') == "%状态" {
+        reply_status().await;
+        return;
+    }
     // Validate danmaku command format: %转播%平台%频道名%分区
     if !normalized_danmaku.contains("%转播%") {
         // tracing::error!("弹幕命令格式错误. Skipping...");
@@ -233,161 +459,129 @@ async fn process_danmaku(command: &str) {
         return;
     }
 
+    if !switch_command_allowed(reader_platform).await {
+        return;
+    }
+
     let platform = parts[2];
     let channel_name = parts[3];
-    let area_name = parts[4];
+    let area_name = parts.get(4).copied().filter(|name| !name.is_empty());
     tracing::info!(
         "平台: {}, 频道: {}, 分区: {}",
         platform,
         channel_name,
-        area_name
+        area_name.unwrap_or("未指定")
     );
-
-    // Determine area_id based on area_name
-    let area_id = match area_name {
-        "英雄联盟" => 86,
-        "无畏契约" => 329,
-        "APEX英雄" => 240,
-        "守望先锋" => 87,
-        "萌宅领域" => 530,
-        "其他单机" => 235,
-        "其他网游" => 107,
-        "UP主日常" => 646,
-        "最终幻想14" => 102,
-        "格斗游戏" => 433,
-        "我的世界" => 216,
-        "DeadLock" => 927,
-        "主机游戏" => 236,
-        "原神" => 321,
-        "斯普拉遁3" => 694,
-        "游戏王：决斗链接" => 407,
-        "逃离塔科夫" => 252,
-        "使命召唤:战区" => 318,
-        _ => {
-            tracing::error!("未知的分区: {}", area_name);
-            return;
+    match switch_channel(platform, channel_name, area_name) {
+        Ok(_) => {
+            *LAST_SWITCH_COMMAND
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now())
         }
-    };
+        Err(e) => tracing::error!("{}", e),
+    }
+}
 
-    if platform.eq("YT") || platform.eq("TW") {
-        let channel_id = match get_channel_id(platform, channel_name) {
-            Ok(id) => id,
+/// Fetches the YouTube topic (or title) or the Twitch title of the channel through the
+/// bilistream CLI, for the keyword and area checks of [`switch_channel`].
+fn fetch_live_title(platform: &str, channel_id: &str) -> Result<String, Box<dyn Error>> {
+    if platform == "YT" {
+        match Command::new("./bilistream")
+            .arg("get-live-topic")
+            .arg("YT")
+            .arg(channel_id)
+            .output()
+        {
+            Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
             Err(e) => {
-                tracing::error!("检查频道时出错: {}", e);
-                return;
+                tracing::error!("获取YT直播分区时出错: {}", e);
+                let output = Command::new("yt-dlp")
+                    .arg("-e")
+                    .arg(format!(
+                        "https://www.youtube.com/channel/{}/live",
+                        channel_id
+                    ))
+                    .output()
+                    .map_err(|e| format!("获取YT直播标题时出错: {}", e))?;
+                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
             }
-        };
-
-        if channel_id.is_none() {
-            tracing::error!("频道 {} 未在{}列表中", channel_name, platform);
-            return;
         }
+    } else {
+        let output = Command::new("./bilistream")
+            .arg("get-live-title")
+            .arg("TW")
+            .arg(channel_id)
+            .output()
+            .map_err(|e| format!("获取TW直播标题时出错: {}", e))?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
 
-        // Use a reference to the String inside channel_id without moving it
-        let channel_id_str = channel_id.as_ref().unwrap();
-
-        let live_title = if platform.eq_ignore_ascii_case("YT") {
-            // get youtube live topic
-            match Command::new("./bilistream")
-                .arg("get-live-topic")
-                .arg("YT")
-                .arg(channel_id_str)
-                .output()
-            {
-                Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
-                Err(e) => {
-                    tracing::error!("获取YT直播分区时出错: {}", e);
-                    match Command::new("yt-dlp")
-                        .arg("-e")
-                        .arg(&format!(
-                            "https://www.youtube.com/channel/{}/live",
-                            channel_id_str
-                        ))
-                        .output()
-                    {
-                        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
-                        Err(e) => {
-                            tracing::error!("获取YT直播标题时出错: {}", e);
-                            return;
-                        }
-                    }
-                }
-            }
-            // match Command::new("yt-dlp")
-            //     .arg("-e")
-            //     .arg(&format!(
-            //         "https://www.youtube.com/channel/{}/live",
-            //         channel_id_str
-            //     ))
-            //     .output()
-            // {
-            //     Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
-            //     Err(e) => {
-            //         tracing::error!("获取YT直播标题时出错: {}", e);
-            //         return;
-            //     }
-            // }
-        } else {
-            // TW
-            match Command::new("./bilistream")
-                .arg("get-live-title")
-                .arg("TW")
-                .arg(channel_id_str)
-                .output()
-            {
-                Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
-                Err(e) => {
-                    tracing::error!("获取TW直播标题时出错: {}", e);
-                    return;
-                }
-            }
-        };
-        println!("{}", live_title);
-        let live_title = live_title.to_lowercase();
-        if live_title.contains("ウォッチパ")
-            || live_title.contains("watchalong")
-            || live_title.contains("talk")
-            || live_title.contains("zatsudan")
-            || live_title.contains("雑談")
-            || live_title.contains("marshmallow")
-            || live_title.contains("morning")
-            || live_title.contains("freechat")
+/// Points `{platform}/config.yaml` at `channel`, a name or ID from
+/// `{platform}_channels.txt`, and at the area named `area_name`, or the configured
+/// area when `None`. The live title may still move it to another area, and banned
+/// keywords and `AreaRestrictions` refuse the switch. The main loop picks the change
+/// up at its next config reload. Returns the channel name and the area ID.
+pub fn switch_channel(
+    platform: &str,
+    channel: &str,
+    area_name: Option<&str>,
+) -> Result<(String, u64), Box<dyn Error>> {
+    if platform != "YT" && platform != "TW" {
+        return Err(format!("不支持的平台: {}", platform).into());
+    }
+    let config_path = format!("{}/config.yaml", platform);
+    let cfg = load_config(Path::new(&config_path), Path::new("cookies.json"))
+        .map_err(|e| format!("读取配置时出错: {}", e))?;
+    let area_id = match area_name {
+        Some(area_name) => get_area_id(area_name).ok_or(format!("未知的分区: {}", area_name))?,
+        None => cfg.bililive.area_v2,
+    };
+    let (channel_name, channel_id) = match get_channel_id(platform, channel)
+        .map_err(|e| format!("检查频道时出错: {}", e))?
+    {
+        Some(channel_id) => (channel.to_string(), channel_id),
+        None => match get_channel_name(platform, channel)
+            .map_err(|e| format!("检查频道时出错: {}", e))?
         {
-            tracing::error!("直播标题/topic包含不支持的关键词");
-            return;
-        }
-        // Now you can use channel_id_str where needed without moving channel_id
-        let new_title = format!("【转播】{}", channel_name);
-        let updated_area_id = check_area_id_with_title(&live_title, area_id);
-        // Additional checks for specific area_ids
-        if (updated_area_id == 240 || updated_area_id == 318) && channel_name != "Kamito" {
-            tracing::error!("只有'Kamito'可以使用 Apex or COD 分区. Skipping...");
-            return;
-        }
-        if let Err(e) = update_config(
-            platform,
-            channel_name,
-            &channel_id_str,
-            &new_title,
-            updated_area_id,
-        ) {
-            tracing::error!("更新配置时出错: {}", e);
-            return;
-        }
-        let updated_area_name = match get_area_name(updated_area_id) {
-            Some(name) => name,
-            None => return, // Early return if the area ID is unknown
-        };
-        tracing::info!(
-            "更新 {} 频道: {} 分区: {} (ID: {} )",
-            platform,
+            Some(channel_name) => (channel_name, channel.to_string()),
+            None => return Err(format!("频道 {} 未在{}列表中", channel, platform).into()),
+        },
+    };
+
+    let live_title = fetch_live_title(platform, &channel_id)?;
+    println!("{}", live_title);
+    // Words that only look banned are replaced instead of refusing the switch
+    let live_title = cfg.bililive.replace_title_words(&live_title).to_lowercase();
+    if let Some(keyword) = find_banned_keyword(&live_title) {
+        return Err(format!("直播标题/topic包含不支持的关键词: {}", keyword.as_str()).into());
+    }
+    let new_title = format!("【转播】{}", channel_name);
+    let updated_area_id = check_area_id_with_title(&live_title, area_id);
+    if !cfg.area_allowed(updated_area_id, &channel_name, &channel_id) {
+        return Err(format!(
+            "{} 不允许使用分区 {}（AreaRestrictions）. Skipping...",
             channel_name,
-            updated_area_name,
-            updated_area_id
-        );
-    } else {
-        tracing::error!("指令错误: {}", danmaku_command);
+            get_area_name(updated_area_id).unwrap_or("未知")
+        )
+        .into());
     }
+    update_config(
+        platform,
+        &channel_name,
+        &channel_id,
+        &new_title,
+        updated_area_id,
+    )
+    .map_err(|e| format!("更新配置时出错: {}", e))?;
+    tracing::info!(
+        "更新 {} 频道: {} 分区: {} (ID: {} )",
+        platform,
+        channel_name,
+        get_area_name(updated_area_id).unwrap_or("未知"),
+        updated_area_id
+    );
+    Ok((channel_name, updated_area_id))
 }
 
 /// Retrieves the room ID from the configuration.
@@ -407,6 +601,33 @@ fn get_room_id() -> String {
     }
 }
 
+/// The live-danmaku-cli started by [`run_danmaku`], kept so that only this copy is
+/// stopped and not the copies other bilistream directories run for their own rooms.
+static DANMAKU_CLI: Mutex<Option<Child>> = Mutex::new(None);
+
+/// Kills and reaps the live-danmaku-cli of this process, if any.
+fn kill_danmaku_cli() {
+    let Some(mut child) = DANMAKU_CLI.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    if let Err(e) = child.kill() {
+        tracing::error!("停止弹幕命令读取失败: {}", e);
+    }
+    let _ = child.wait();
+}
+
+/// Stops the danmaku-cli started by [`run_danmaku`] for `platform`, e.g. on exit.
+pub fn stop_danmaku(platform: &str) {
+    if !Path::new(&format!("danmaku.lock-{}", platform)).exists() {
+        return;
+    }
+    kill_danmaku_cli();
+    DANMAKU_CONNECTED.store(false, Ordering::Relaxed);
+    if let Err(e) = remove_danmaku_lock() {
+        tracing::error!("删除弹幕锁文件失败: {}", e);
+    }
+}
+
 /// Main function to execute danmaku processing.
 pub fn run_danmaku(platform: &str) {
     // Check if any danmaku is already running
@@ -431,46 +652,53 @@ pub fn run_danmaku(platform: &str) {
         .output()
         .expect("更新sessdata失败");
     // Start danmaku-cli in background
-    let danmaku_cli = Command::new("./live-danmaku-cli")
-        .arg("--config")
-        .arg("config.json")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("启动弹幕命令读取失败");
-
-    let stdout = danmaku_cli.stdout.expect("捕获stdout失败");
-    let stderr = danmaku_cli.stderr.expect("捕获stderr失败");
-
-    // Handle stdout in a separate thread
-    thread::spawn(move || {
-        let reader = io::BufReader::new(stdout);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                // Process each danmaku command
-                tokio::runtime::Runtime::new()
-                    .unwrap()
-                    .block_on(process_danmaku(&line));
-            }
-        }
-    });
-
-    // Handle stderr in a separate thread
-    thread::spawn(move || {
-        let reader = io::BufReader::new(stderr);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                eprintln!("弹幕stderr: {}", line);
-            }
-        }
-    });
+    DANMAKU_COOKIE_EXPIRED.store(false, Ordering::Relaxed);
+    let sample_file = cfg.bililive.danmaku_sample_file.clone();
+    let danmaku_cli =
+        spawn_danmaku_cli(platform, sample_file.clone()).expect("启动弹幕命令读取失败");
+    *DANMAKU_CLI.lock().unwrap_or_else(|e| e.into_inner()) = Some(danmaku_cli);
 
     tracing::info!("弹幕命令读取已在进程 bilistream-{} 中执行", platform);
 
     // Monitor Bilibili live status every 300 seconds
+    let mut failures = 0;
     loop {
         thread::sleep(Duration::from_secs(60));
 
+        // Restart live-danmaku-cli when it died, unless the cookie was rejected
+        let status = match DANMAKU_CLI
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            Some(danmaku_cli) => danmaku_cli.try_wait(),
+            // Taken by stop_danmaku
+            None => return,
+        };
+        match status {
+            Ok(None) => failures = 0,
+            Ok(Some(_)) if DANMAKU_COOKIE_EXPIRED.load(Ordering::Relaxed) => {
+                DANMAKU_CONNECTED.store(false, Ordering::Relaxed);
+            }
+            Ok(Some(_)) => {
+                DANMAKU_CONNECTED.store(false, Ordering::Relaxed);
+                failures += 1;
+                DANMAKU_RECONNECTS.fetch_add(1, Ordering::Relaxed);
+                if failures % DANMAKU_FAILURE_ALERT == 0 {
+                    tracing::error!("弹幕连接持续失败（连续 {} 次），请检查 cookie", failures);
+                } else {
+                    tracing::warn!("弹幕连接已断开，正在重连");
+                }
+                match spawn_danmaku_cli(platform, sample_file.clone()) {
+                    Ok(child) => {
+                        *DANMAKU_CLI.lock().unwrap_or_else(|e| e.into_inner()) = Some(child)
+                    }
+                    Err(e) => tracing::error!("重启弹幕命令读取失败: {}", e),
+                }
+            }
+            Err(e) => tracing::error!("检查弹幕命令读取状态失败: {}", e),
+        }
+
         let room_id = get_room_id();
 
         if room_id.is_empty() {
@@ -492,47 +720,15 @@ pub fn run_danmaku(platform: &str) {
             }
         };
 
-        if !bilibili_status.contains("未直播") {
-            if ffmpeg::is_any_ffmpeg_running() {
-                tracing::info!("ffmpeg 正在运行. 停止弹幕命令读取...");
-                // Kill danmaku-cli process
-                Command::new("pkill")
-                    .arg("-f")
-                    .arg("danmaku-cli")
-                    .output()
-                    .expect("停止弹幕命令读取失败");
-
-                // Try to remove both lock files, logging any errors
-                remove_danmaku_lock().expect("删除弹幕锁文件失败");
-                break;
-            }
+        if !bilibili_status.contains("未直播") && ffmpeg::is_any_ffmpeg_running() {
+            break;
         }
     }
-}
 
-pub fn get_area_name(area_id: u64) -> Option<&'static str> {
-    match area_id {
-        86 => Some("英雄联盟"),
-        329 => Some("无畏契约"),
-        240 => Some("APEX英雄"),
-        87 => Some("守望先锋"),
-        235 => Some("其他单机"),
-        107 => Some("其他网游"),
-        530 => Some("萌宅领域"),
-        236 => Some("主机游戏"),
-        321 => Some("原神"),
-        694 => Some("斯普拉遁3"),
-        407 => Some("游戏王：决斗链接"),
-        433 => Some("格斗游戏"),
-        927 => Some("DeadLock"),
-        216 => Some("我的世界"),
-        646 => Some("UP主日常"),
-        102 => Some("最终幻想14"),
-        252 => Some("逃离塔科夫"),
-        318 => Some("使命召唤:战区"),
-        _ => {
-            tracing::error!("未知的分区ID: {}", area_id);
-            None
-        }
-    }
+    tracing::info!("ffmpeg 正在运行. 停止弹幕命令读取...");
+    kill_danmaku_cli();
+    DANMAKU_CONNECTED.store(false, Ordering::Relaxed);
+
+    // Try to remove both lock files, logging any errors
+    remove_danmaku_lock().expect("删除弹幕锁文件失败");
 }
@@ -0,0 +1,168 @@
+use serde_yaml::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Config keys whose values are replaced before they are written to the bundle.
+/// `Url` is the push address of an `ExtraRtmpTargets` entry, which holds its key.
+const SENSITIVE_KEYS: [&str; 7] = [
+    "BiliRtmpKey",
+    "HolodexApiKey",
+    "RiotApiKey",
+    "OauthToken",
+    "Proxy",
+    "TelegramBotToken",
+    "Url",
+];
+
+/// How many of the last log lines go into the bundle.
+const LOG_TAIL_LINES: usize = 500;
+
+/// Masks every sensitive value in a YAML document, recursing into nested maps and lists.
+fn mask_sensitive(value: &mut Value) {
+    if let Value::Sequence(items) = value {
+        items.iter_mut().for_each(mask_sensitive);
+    }
+    if let Value::Mapping(map) = value {
+        for (key, val) in map.iter_mut() {
+            let is_sensitive = key
+                .as_str()
+                .is_some_and(|key| SENSITIVE_KEYS.contains(&key));
+            if is_sensitive && !val.is_null() {
+                *val = Value::String("***".to_string());
+            } else {
+                mask_sensitive(val);
+            }
+        }
+    }
+}
+
+/// Returns the first line printed by `program args`, or why it could not run.
+fn command_first_line(program: &str, args: &[&str]) -> String {
+    match Command::new(program).args(args).output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            stdout
+                .lines()
+                .chain(stderr.lines())
+                .next()
+                .unwrap_or("")
+                .to_string()
+        }
+        Err(e) => format!("无法执行: {}", e),
+    }
+}
+
+fn versions() -> String {
+    [
+        format!("bilistream: {}", env!("CARGO_PKG_VERSION")),
+        format!("ffmpeg: {}", command_first_line("ffmpeg", &["-version"])),
+        format!("yt-dlp: {}", command_first_line("yt-dlp", &["--version"])),
+        format!(
+            "streamlink: {}",
+            command_first_line("streamlink", &["--version"])
+        ),
+        format!("python3: {}", command_first_line("python3", &["--version"])),
+    ]
+    .join("\n")
+}
+
+fn system_info() -> String {
+    let cpu = fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split(':').nth(1))
+                .map(|model| model.trim().to_string())
+        })
+        .unwrap_or_else(|| "未知".to_string());
+    let memory = fs::read_to_string("/proc/meminfo")
+        .map(|info| info.lines().take(3).collect::<Vec<_>>().join("\n"))
+        .unwrap_or_else(|_| "未知".to_string());
+    format!(
+        "os: {} {}\nkernel: {}\ncpu: {} ({} 线程)\n{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        command_first_line("uname", &["-a"]),
+        cpu,
+        std::thread::available_parallelism().map_or(0, |n| n.get()),
+        memory
+    )
+}
+
+/// Writes a diagnostics bundle and returns the path of the archive.
+///
+/// The bundle holds the masked YT/TW configs, dependency versions, system
+/// information and, when `log_path` is given, the tail of that log together
+/// with the ffmpeg error lines found in it.
+pub fn create_diagnose_bundle(log_path: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let name = format!(
+        "bilistream-diagnose-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    );
+    let dir = Path::new(&name);
+    fs::create_dir_all(dir)?;
+
+    for platform in ["YT", "TW"] {
+        let config_path = format!("{}/config.yaml", platform);
+        let masked = match fs::read_to_string(&config_path) {
+            Ok(content) => match serde_yaml::from_str::<Value>(&content) {
+                Ok(mut value) => {
+                    mask_sensitive(&mut value);
+                    serde_yaml::to_string(&value)?
+                }
+                // Never copy a config we could not mask
+                Err(e) => format!("# 解析 {} 失败: {}", config_path, e),
+            },
+            Err(e) => format!("# 读取 {} 失败: {}", config_path, e),
+        };
+        fs::write(dir.join(format!("config-{}.yaml", platform)), masked)?;
+    }
+
+    fs::write(dir.join("versions.txt"), versions())?;
+    fs::write(dir.join("system.txt"), system_info())?;
+
+    if let Some(log_path) = log_path {
+        let log = fs::read_to_string(log_path)?;
+        let lines: Vec<&str> = log.lines().collect();
+        let tail = &lines[lines.len().saturating_sub(LOG_TAIL_LINES)..];
+        fs::write(dir.join("log-tail.txt"), tail.join("\n"))?;
+        let ffmpeg_errors: Vec<&str> = lines
+            .iter()
+            .filter(|line| {
+                line.contains("ffmpeg错误统计")
+                    || (line.contains("ffmpeg")
+                        && (line.contains("ERROR") || line.contains("失败")))
+            })
+            .copied()
+            .collect();
+        fs::write(dir.join("ffmpeg-errors.txt"), ffmpeg_errors.join("\n"))?;
+    }
+
+    // Prefer zip, fall back to tar.gz where zip is not installed
+    let archive = if Command::new("zip")
+        .arg("-qr")
+        .arg(format!("{}.zip", name))
+        .arg(&name)
+        .status()
+        .is_ok_and(|status| status.success())
+    {
+        format!("{}.zip", name)
+    } else {
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(format!("{}.tar.gz", name))
+            .arg(&name)
+            .status()?;
+        if !status.success() {
+            return Err("打包诊断文件失败".into());
+        }
+        format!("{}.tar.gz", name)
+    };
+    fs::remove_dir_all(dir)?;
+
+    Ok(archive)
+}
@@ -0,0 +1,121 @@
+use crate::plugins::ffmpeg::{hw_accel_encoder, list_ffmpeg_encoders, HW_ENCODERS};
+use std::path::Path;
+use std::process::Command;
+
+/// GPU vendors that have a matching hardware encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GpuVendor {
+    Nvidia,
+    Intel,
+    Amd,
+}
+
+impl GpuVendor {
+    fn name(&self) -> &'static str {
+        match self {
+            GpuVendor::Nvidia => "NVIDIA",
+            GpuVendor::Intel => "Intel",
+            GpuVendor::Amd => "AMD",
+        }
+    }
+
+    /// Encoders that work on this vendor, most preferred first.
+    fn encoders(&self) -> &'static [&'static str] {
+        match self {
+            GpuVendor::Nvidia => &["h264_nvenc"],
+            GpuVendor::Intel => &["h264_qsv", "h264_vaapi"],
+            GpuVendor::Amd => &["h264_vaapi"],
+        }
+    }
+}
+
+/// Detects GPUs from `nvidia-smi` and `lspci`. Missing tools simply detect nothing.
+fn detect_gpus() -> Vec<GpuVendor> {
+    let mut gpus = Vec::new();
+    if Command::new("nvidia-smi")
+        .arg("-L")
+        .output()
+        .is_ok_and(|output| output.status.success())
+    {
+        gpus.push(GpuVendor::Nvidia);
+    }
+    if let Ok(output) = Command::new("lspci").output() {
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if !(line.contains("VGA") || line.contains("3D controller") || line.contains("Display"))
+            {
+                continue;
+            }
+            let line = line.to_lowercase();
+            let vendor = if line.contains("nvidia") {
+                GpuVendor::Nvidia
+            } else if line.contains("intel") {
+                GpuVendor::Intel
+            } else if line.contains("amd") || line.contains("ati") {
+                GpuVendor::Amd
+            } else {
+                continue;
+            };
+            if !gpus.contains(&vendor) {
+                gpus.push(vendor);
+            }
+        }
+    }
+    gpus
+}
+
+/// Checks the GPUs and ffmpeg encoders of this machine and recommends a hardware encoder.
+pub fn run_doctor() -> String {
+    let mut report = Vec::new();
+
+    let gpus = detect_gpus();
+    if gpus.is_empty() {
+        report.push("显卡: 未检测到 (nvidia-smi / lspci 不可用或无独立显卡)".to_string());
+    } else {
+        let names: Vec<&str> = gpus.iter().map(|gpu| gpu.name()).collect();
+        report.push(format!("显卡: {}", names.join(", ")));
+    }
+    if Path::new("/dev/dri/renderD128").exists() {
+        report.push("VAAPI 设备: /dev/dri/renderD128".to_string());
+    }
+
+    let encoders = list_ffmpeg_encoders();
+    if encoders.is_empty() {
+        report.push("ffmpeg: 无法执行 ffmpeg -encoders，请确认 ffmpeg 已安装".to_string());
+        return report.join("\n");
+    }
+    let hw_available: Vec<&str> = HW_ENCODERS
+        .iter()
+        .copied()
+        .filter(|encoder| encoders.iter().any(|e| e == encoder))
+        .collect();
+    report.push(format!(
+        "ffmpeg 硬件编码器: {}",
+        if hw_available.is_empty() {
+            "无".to_string()
+        } else {
+            hw_available.join(", ")
+        }
+    ));
+
+    // Only recommend an encoder that both ffmpeg and the detected GPU support
+    let recommended = gpus
+        .iter()
+        .flat_map(|gpu| gpu.encoders().iter().copied())
+        .find(|encoder| hw_available.contains(encoder));
+    match recommended {
+        Some(encoder) => {
+            report.push(format!("推荐编码器: {}", encoder));
+            if let Some(hw_accel) = ["nvenc", "qsv", "vaapi"]
+                .into_iter()
+                .find(|hw_accel| hw_accel_encoder(hw_accel) == Some(encoder))
+            {
+                report.push(format!(
+                    "在 FfmpegOptions 中设置 Copy: false 和 HwAccel: {} 即可使用",
+                    hw_accel
+                ));
+            }
+        }
+        None => report.push("推荐编码器: libx264 (软件编码，未找到可用的硬件编码)".to_string()),
+    }
+    report.join("\n")
+}
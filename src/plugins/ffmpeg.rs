@@ -1,13 +1,466 @@
+use crate::config::{FfmpegOptions, Placeholder, Ticker, Watermark};
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// ffmpeg messages that indicate the audio and video timestamps drift apart.
+const DESYNC_PATTERNS: [&str; 4] = [
+    "Non-monotonous DTS",
+    "Non-monotonic DTS",
+    "non monotonically increasing dts",
+    "Queue input is backward in time",
+];
+
+/// File the ticker text is written to, read by ffmpeg's drawtext filter.
+const TICKER_TEXT_FILE: &str = "ticker.txt";
+
+/// Builds the drawtext filter for a scrolling ticker. The text goes through a file
+/// so it needs no filter escaping.
+fn ticker_filter(ticker: &Ticker) -> std::io::Result<String> {
+    fs::write(TICKER_TEXT_FILE, &ticker.text)?;
+    let y = if ticker.position == "top" {
+        "20"
+    } else {
+        "h-th-20"
+    };
+    let mut filter = format!(
+        "drawtext=textfile={}:fontsize={}:fontcolor=white:box=1:boxcolor=black@0.5:x=w-mod(t*{}\\,w+tw):y={}",
+        TICKER_TEXT_FILE, ticker.font_size, ticker.speed, y
+    );
+    if let Some(font_file) = &ticker.font_file {
+        filter.push_str(&format!(":fontfile={}", font_file));
+    }
+    Ok(filter)
+}
+
+/// File the watermark text is written to, read by ffmpeg's drawtext filter.
+const WATERMARK_TEXT_FILE: &str = "watermark.txt";
+
+/// Builds the drawtext filter for a moving watermark. Positions are computed by ffmpeg
+/// from the frame time, so it needs no restart to move.
+fn watermark_filter(watermark: &Watermark) -> std::io::Result<String> {
+    fs::write(WATERMARK_TEXT_FILE, &watermark.text)?;
+    let (x, y) = if watermark.mode == "bounce" {
+        (
+            "abs(mod(t*60\\,2*max(w-tw\\,1))-max(w-tw\\,1))".to_string(),
+            "abs(mod(t*40\\,2*max(h-th\\,1))-max(h-th\\,1))".to_string(),
+        )
+    } else {
+        // Large primes spread consecutive slots over the whole picture
+        let slot = format!("trunc(t/{})", watermark.interval.max(1));
+        (
+            format!("mod({}*7919\\,max(w-tw\\,1))", slot),
+            format!("mod({}*4093\\,max(h-th\\,1))", slot),
+        )
+    };
+    let mut filter = format!(
+        "drawtext=textfile={}:fontsize={}:fontcolor=white@0.6:shadowcolor=black@0.6:shadowx=2:shadowy=2:x={}:y={}",
+        WATERMARK_TEXT_FILE, watermark.font_size, x, y
+    );
+    if let Some(font_file) = &watermark.font_file {
+        filter.push_str(&format!(":fontfile={}", font_file));
+    }
+    Ok(filter)
+}
+
+/// Warn again every time this many more timestamp warnings were seen.
+const DESYNC_WARN_THRESHOLD: u32 = 20;
+
+/// Counts ffmpeg timestamp warnings and raises a warning when they pile up.
+#[derive(Default)]
+struct DesyncMonitor {
+    count: u32,
+}
+
+impl DesyncMonitor {
+    fn inspect(&mut self, line: &str) {
+        if !DESYNC_PATTERNS.iter().any(|pattern| line.contains(pattern)) {
+            return;
+        }
+        self.count += 1;
+        if self.count.is_multiple_of(DESYNC_WARN_THRESHOLD) {
+            tracing::warn!(
+                "ffmpeg已报告 {} 次时间戳异常，可能出现音画不同步，可尝试关闭 FfmpegOptions.Copy 转码推流",
+                self.count
+            );
+        }
+    }
+}
+
+/// Error kinds counted for every ffmpeg run: name, stderr patterns, and the hint shown
+/// when the kind shows up at least [`ERROR_HINT_THRESHOLD`] times.
+const ERROR_KINDS: [(&str, &[&str], &str); 4] = [
+    (
+        "断连",
+        &[
+            "Connection reset by peer",
+            "Broken pipe",
+            "Connection refused",
+        ],
+        "频繁断连，建议更换代理或检查网络",
+    ),
+    (
+        "超时",
+        &["timed out", "Timeout", "timeout"],
+        "频繁超时，建议更换代理或降低画质",
+    ),
+    (
+        "时间戳异常",
+        &DESYNC_PATTERNS,
+        "时间戳频繁异常，建议关闭 FfmpegOptions.Copy 转码推流",
+    ),
+    (
+        "数据损坏",
+        &["corrupt", "Invalid data found", "error while decoding"],
+        "源数据频繁损坏，建议降低画质或稍后重试",
+    ),
+];
+const ERROR_HINT_THRESHOLD: u32 = 10;
+
+/// Counts ffmpeg's stderr lines per [`ERROR_KINDS`] entry.
+#[derive(Default)]
+struct ErrorStats {
+    counts: [u32; ERROR_KINDS.len()],
+}
+
+impl ErrorStats {
+    fn inspect(&mut self, line: &str) {
+        if let Some(kind) = ERROR_KINDS
+            .iter()
+            .position(|(_, patterns, _)| patterns.iter().any(|pattern| line.contains(pattern)))
+        {
+            self.counts[kind] += 1;
+        }
+    }
+
+    /// Logs the counts of this run and a hint for every kind that kept showing up.
+    fn report(&self) {
+        if self.counts.iter().all(|&count| count == 0) {
+            return;
+        }
+        let summary: Vec<String> = ERROR_KINDS
+            .iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|((name, _, _), count)| format!("{} {}次", name, count))
+            .collect();
+        tracing::info!("本次推流ffmpeg错误统计: {}", summary.join(", "));
+        for ((_, _, hint), count) in ERROR_KINDS.iter().zip(self.counts) {
+            if count >= ERROR_HINT_THRESHOLD {
+                tracing::warn!("{}", hint);
+            }
+        }
+    }
+}
+
+/// Below this `speed=` ffmpeg cannot keep up with the live source.
+const SLOW_SPEED: f32 = 0.94;
+/// How long ffmpeg has to stay slow before the input quality is lowered.
+const SLOW_DURATION: Duration = Duration::from_secs(30);
+
+/// Input quality steps, from the source quality down.
+pub const QUALITY_LEVELS: [&str; 3] = ["best", "720p", "480p"];
+
+/// Index into [`QUALITY_LEVELS`] the source url should currently be resolved with.
+static QUALITY_LEVEL: AtomicU8 = AtomicU8::new(0);
+/// Set when the quality was lowered and nobody announced it yet.
+static QUALITY_DOWNGRADED: AtomicBool = AtomicBool::new(false);
+/// Last `speed=` of the running ffmpeg, as f32 bits.
+static LAST_SPEED: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the last speed reported by the ffmpeg of this process, if one is running.
+pub fn current_speed() -> Option<f32> {
+    RUNNING_FFMPEG
+        .lock()
+        .unwrap()
+        .is_some()
+        .then(|| f32::from_bits(LAST_SPEED.load(Ordering::Relaxed)))
+}
+
+/// How often the speed is written to `ffmpeg.speed-{platform}`.
+const SPEED_FILE_INTERVAL: Duration = Duration::from_secs(5);
+/// A speed file not updated for this long means ffmpeg stalled.
+const SPEED_FILE_STALE: Duration = Duration::from_secs(30);
+
+/// Returns the last speed of the ffmpeg pushing for `platform`, from any process, or
+/// `Some(0.0)` while it is still connecting or stalled. None when it is not running.
+pub fn streaming_speed(platform: &str) -> Option<f32> {
+    if !Path::new(&format!("ffmpeg.lock-{}", platform)).exists() {
+        return None;
+    }
+    let speed_file = format!("ffmpeg.speed-{}", platform);
+    let fresh = fs::metadata(&speed_file)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < SPEED_FILE_STALE));
+    Some(
+        fresh
+            .then(|| fs::read_to_string(&speed_file).ok())
+            .flatten()
+            .and_then(|speed| speed.trim().parse().ok())
+            .unwrap_or(0.0),
+    )
+}
+
+/// How smooth the push is at `speed`, as shown to viewers by `%状态`.
+pub fn describe_speed(speed: f32) -> &'static str {
+    if speed >= 0.98 {
+        "流畅"
+    } else if speed >= SLOW_SPEED {
+        "波动"
+    } else {
+        "卡顿"
+    }
+}
+
+/// Returns the current input quality step, see [`QUALITY_LEVELS`].
+pub fn quality_level() -> usize {
+    QUALITY_LEVEL.load(Ordering::Relaxed) as usize
+}
+
+/// Goes back to the source quality, called when a new stream starts.
+pub fn reset_quality_level() {
+    QUALITY_LEVEL.store(0, Ordering::Relaxed);
+    QUALITY_DOWNGRADED.store(false, Ordering::Relaxed);
+}
+
+/// Returns true once after the quality was lowered.
+pub fn take_quality_downgraded() -> bool {
+    QUALITY_DOWNGRADED.swap(false, Ordering::Relaxed)
+}
+
+/// Watches the `speed=` of ffmpeg's -stats lines, and restarts ffmpeg with a lower input
+/// quality when it stays below [`SLOW_SPEED`] for [`SLOW_DURATION`]. The speed is also
+/// written to `ffmpeg.speed-{platform}` for the danmaku reader of other processes.
+struct SpeedMonitor {
+    slow_since: Option<Instant>,
+    speed_file: String,
+    last_written: Option<Instant>,
+}
+
+impl SpeedMonitor {
+    fn new(platform: &str) -> Self {
+        SpeedMonitor {
+            slow_since: None,
+            speed_file: format!("ffmpeg.speed-{}", platform),
+            last_written: None,
+        }
+    }
+
+    fn inspect(&mut self, line: &str) {
+        let Some(speed) = line
+            .split("speed=")
+            .nth(1)
+            .and_then(|rest| rest.trim_start().split('x').next())
+            .and_then(|speed| speed.trim().parse::<f32>().ok())
+        else {
+            return;
+        };
+        LAST_SPEED.store(speed.to_bits(), Ordering::Relaxed);
+        if self
+            .last_written
+            .is_none_or(|written| written.elapsed() >= SPEED_FILE_INTERVAL)
+        {
+            let _ = fs::write(&self.speed_file, speed.to_string());
+            self.last_written = Some(Instant::now());
+        }
+        if speed >= SLOW_SPEED {
+            self.slow_since = None;
+            return;
+        }
+        let slow_since = *self.slow_since.get_or_insert_with(Instant::now);
+        if slow_since.elapsed() < SLOW_DURATION {
+            return;
+        }
+        self.slow_since = None;
+        let level = quality_level();
+        if level + 1 >= QUALITY_LEVELS.len() {
+            return;
+        }
+        QUALITY_LEVEL.store(level as u8 + 1, Ordering::Relaxed);
+        QUALITY_DOWNGRADED.store(true, Ordering::Relaxed);
+        tracing::warn!(
+            "推流速度持续低于 {}x，降低画质到 {} 后重启ffmpeg",
+            SLOW_SPEED,
+            QUALITY_LEVELS[level + 1]
+        );
+        stop_ffmpeg();
+    }
+}
+
+/// Passes ffmpeg's stderr through unchanged while checking each line for timestamp warnings.
+/// Returns how many timestamp warnings were seen once ffmpeg closes stderr.
+fn watch_ffmpeg_stderr(mut stderr: impl Read, platform: &str) -> u32 {
+    let mut monitor = DesyncMonitor::default();
+    let mut speed = SpeedMonitor::new(platform);
+    let mut errors = ErrorStats::default();
+    let mut line = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stderr.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let _ = io::stderr().write_all(&buf[..n]);
+        // -stats progress lines end with \r instead of \n
+        for &byte in &buf[..n] {
+            if byte == b'\n' || byte == b'\r' {
+                let text = String::from_utf8_lossy(&line);
+                monitor.inspect(&text);
+                speed.inspect(&text);
+                errors.inspect(&text);
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+    }
+    errors.report();
+    monitor.count
+}
+
+/// Hardware H.264 encoders bilistream knows how to use, most preferred first.
+pub const HW_ENCODERS: [&str; 3] = ["h264_nvenc", "h264_qsv", "h264_vaapi"];
+
+/// Render node passed to `-vaapi_device` for `HwAccel: vaapi`.
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Encoders reported by ffmpeg, probed once on first use.
+static AVAILABLE_ENCODERS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Maps a `FfmpegOptions.HwAccel` value to its ffmpeg encoder.
+pub fn hw_accel_encoder(hw_accel: &str) -> Option<&'static str> {
+    match hw_accel {
+        "nvenc" => Some("h264_nvenc"),
+        "qsv" => Some("h264_qsv"),
+        "vaapi" => Some("h264_vaapi"),
+        _ => None,
+    }
+}
+
+/// Returns the video encoder used when transcoding: libx264, or the `HwAccel` encoder.
+/// `None` means the configured hardware encoder is unknown or missing from this ffmpeg.
+pub fn video_encoder(options: &FfmpegOptions) -> Option<&'static str> {
+    let Some(hw_accel) = options.hw_accel.as_deref() else {
+        return Some("libx264");
+    };
+    let encoder = hw_accel_encoder(hw_accel)?;
+    AVAILABLE_ENCODERS
+        .get_or_init(list_ffmpeg_encoders)
+        .iter()
+        .any(|e| e == encoder)
+        .then_some(encoder)
+}
+
+/// Lists the encoder names reported by `ffmpeg -encoders`. Empty when ffmpeg cannot run.
+pub fn list_ffmpeg_encoders() -> Vec<String> {
+    let output = match Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-encoders")
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+    // Lines look like " V....D h264_nvenc           NVIDIA NVENC H.264 encoder"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            let name = fields.next()?;
+            (flags.len() == 6 && flags != "------").then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// Resolution, frame rate and bitrate of a source stream, as found by ffprobe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceQuality {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    /// Bits per second, not every HLS stream reports it.
+    pub bitrate: Option<u64>,
+}
+
+impl std::fmt::Display for SourceQuality {
+    /// Shown like `1080p60 6.0Mbps`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}p{}", self.height, self.fps.round() as u32)?;
+        if let Some(bitrate) = self.bitrate {
+            write!(f, " {:.1}Mbps", bitrate as f64 / 1_000_000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Last probed live and its quality, so a restart of the same live is not probed again.
+static SOURCE_QUALITY: Mutex<Option<(String, SourceQuality)>> = Mutex::new(None);
+
+/// Probes the video stream of `url` with ffprobe. `live_key` identifies the live
+/// (e.g. the channel id), the result is cached for it since m3u8 urls change on
+/// every fetch. Returns `None` when ffprobe is missing or cannot read the stream.
+pub fn probe_source_quality(
+    live_key: &str,
+    url: &str,
+    proxy: Option<&str>,
+) -> Option<SourceQuality> {
+    if let Some((key, quality)) = SOURCE_QUALITY.lock().unwrap().as_ref() {
+        if key == live_key {
+            return Some(quality.clone());
+        }
+    }
+    let mut command = Command::new("ffprobe");
+    command.args(["-v", "error", "-rw_timeout", "15000000"]);
+    if let Some(proxy) = proxy {
+        command.arg("-http_proxy").arg(proxy);
+    }
+    let output = command
+        .args(["-select_streams", "v:0"])
+        .args([
+            "-show_entries",
+            "stream=width,height,avg_frame_rate,bit_rate:format=bit_rate",
+        ])
+        .args(["-of", "json"])
+        .arg(url)
+        .output()
+        .ok()?;
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    let stream = json["streams"].get(0)?;
+    // Frame rates come as a fraction, e.g. "30000/1001"
+    let fps = stream["avg_frame_rate"].as_str().and_then(|rate| {
+        let (num, den) = rate.split_once('/')?;
+        let (num, den) = (num.parse::<f32>().ok()?, den.parse::<f32>().ok()?);
+        (den > 0.0).then(|| num / den)
+    });
+    let bitrate = [&stream["bit_rate"], &json["format"]["bit_rate"]]
+        .iter()
+        .find_map(|value| value.as_str()?.parse().ok());
+    let quality = SourceQuality {
+        width: stream["width"].as_u64()? as u32,
+        height: stream["height"].as_u64()? as u32,
+        fps: fps.unwrap_or(0.0),
+        bitrate,
+    };
+    *SOURCE_QUALITY.lock().unwrap() = Some((live_key.to_string(), quality.clone()));
+    Some(quality)
+}
+
+/// Forgets the cached [`SourceQuality`], called once the source is offline.
+pub fn clear_source_quality() {
+    *SOURCE_QUALITY.lock().unwrap() = None;
+}
 
 /// Checks if any ffmpeg lock file exists.
 pub fn is_any_ffmpeg_running() -> bool {
     Path::new("ffmpeg.lock-YT").exists() || Path::new("ffmpeg.lock-TW").exists()
 }
 
-/// Checks if the ffmpeg lock file for the specified platform exists.
+// Checks if the ffmpeg lock file for the specified platform exists.
 // pub fn is_ffmpeg_running(platform: &str) -> bool {
 //     let lock_file = format!("ffmpeg.lock-{}", platform);
 //     Path::new(&lock_file).exists()
@@ -21,14 +474,530 @@ pub fn create_ffmpeg_lock(platform: &str) -> std::io::Result<()> {
     Ok(())
 }
 
-/// Removes the ffmpeg lock file for the specified platform.
+/// Asks the process `pid` to exit (SIGTERM), without relying on a `kill` binary.
+#[cfg(unix)]
+fn kill_process(pid: u32) -> io::Result<()> {
+    let pid = libc::pid_t::try_from(pid).map_err(io::Error::other)?;
+    // SAFETY: kill only sends a signal, it does not touch memory of this process
+    if unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Terminates the process `pid` through the Win32 API.
+#[cfg(windows)]
+fn kill_process(pid: u32) -> io::Result<()> {
+    use std::ffi::c_void;
+    const PROCESS_TERMINATE: u32 = 0x0001;
+    extern "system" {
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut c_void;
+        fn TerminateProcess(process: *mut c_void, exit_code: u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+    // SAFETY: the handle is checked for null and closed before returning
+    unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let terminated = TerminateProcess(process, 1) != 0;
+        let error = io::Error::last_os_error();
+        CloseHandle(process);
+        if terminated {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Kills the ffmpeg of whichever bilistream process is streaming, using the pid kept
+/// in its lock file, or the child handle when this process started it. Returns the
+/// platform that was streaming.
+pub fn stop_ffmpeg_process() -> Option<&'static str> {
+    for platform in ["YT", "TW"] {
+        let lock_file = format!("ffmpeg.lock-{}", platform);
+        let Ok(pid) = fs::read_to_string(&lock_file) else {
+            continue;
+        };
+        let Ok(pid) = pid.trim().parse::<u32>() else {
+            continue;
+        };
+        let own_child = RUNNING_FFMPEG
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|child| child.id() == pid);
+        if own_child && stop_ffmpeg() {
+            tracing::info!("已停止{}的ffmpeg (pid {})", platform, pid);
+            return Some(platform);
+        }
+        match kill_process(pid) {
+            Ok(()) => {
+                tracing::info!("已停止{}的ffmpeg (pid {})", platform, pid);
+                return Some(platform);
+            }
+            Err(e) => tracing::error!("停止ffmpeg失败 (pid {}): {}", pid, e),
+        }
+    }
+    None
+}
+
+/// A recording of the source made while the rebroadcast is cut off, see
+/// `BiliLive.GapRecordDir`. The recording stops when this is dropped.
+pub struct GapRecording {
+    child: Child,
+    pub path: PathBuf,
+}
+
+impl Drop for GapRecording {
+    fn drop(&mut self) {
+        // MPEG-TS stays playable when ffmpeg is killed mid-write
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Starts saving `url` as `gap-<time>.ts` in `dir` with a separate ffmpeg.
+pub fn start_gap_recording(dir: &str, url: &str, proxy: Option<&str>) -> io::Result<GapRecording> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!(
+        "gap-{}.ts",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-loglevel", "error", "-y"]);
+    if let Some(proxy) = proxy {
+        command.arg("-http_proxy").arg(proxy);
+    }
+    let child = command
+        .arg("-i")
+        .arg(url)
+        .args(["-c", "copy", "-f", "mpegts"])
+        .arg(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(GapRecording { child, path })
+}
+
+/// Holds the platform whose rebroadcast was stopped with `stop-live`.
+const MANUAL_STOP_FILE: &str = "manual_stop";
+
+/// Marks the rebroadcast of `platform` as stopped by hand, so it is not started
+/// again while the same source live goes on.
+pub fn set_manual_stop(platform: &str) -> std::io::Result<()> {
+    fs::write(MANUAL_STOP_FILE, platform)
+}
+
+pub fn manual_stop_requested(platform: &str) -> bool {
+    fs::read_to_string(MANUAL_STOP_FILE).is_ok_and(|content| content.trim() == platform)
+}
+
+pub fn clear_manual_stop() {
+    if fs::remove_file(MANUAL_STOP_FILE).is_ok() {
+        tracing::info!("已清除手动停止标记");
+    }
+}
+
+/// Removes the ffmpeg lock file and the speed file for the specified platform.
 pub fn remove_ffmpeg_lock(platform: &str) -> std::io::Result<()> {
+    let _ = fs::remove_file(format!("ffmpeg.speed-{}", platform));
     let lock_file = format!("ffmpeg.lock-{}", platform);
     fs::remove_file(&lock_file)?;
     tracing::info!("{} 删除成功", lock_file);
     Ok(())
 }
 
+/// Renders `FfmpegCommandTemplate` into ffmpeg arguments.
+///
+/// `{input}`, `{output}` and `{proxy}` are replaced inside every element. Without a
+/// proxy, an element that is exactly `{proxy}` is dropped together with the option
+/// right before it, so `["-http_proxy", "{proxy}"]` disappears as a whole.
+fn render_command_template(
+    template: &[String],
+    input: &str,
+    output: &str,
+    proxy: Option<&str>,
+) -> Vec<String> {
+    let mut args: Vec<String> = Vec::with_capacity(template.len());
+    for arg in template {
+        if proxy.is_none() && arg == "{proxy}" {
+            if args.last().is_some_and(|prev| prev.starts_with('-')) {
+                args.pop();
+            }
+            continue;
+        }
+        args.push(
+            arg.replace("{input}", input)
+                .replace("{output}", output)
+                .replace("{proxy}", proxy.unwrap_or("")),
+        );
+    }
+    args
+}
+
+/// Muxer of the Bilibili output: MPEG-TS with the libsrt `latency` (in microseconds)
+/// over SRT, FLV over RTMP.
+fn add_output_format(command: &mut Command, srt_latency: Option<u64>) {
+    match srt_latency {
+        Some(latency) => {
+            command
+                .arg("-f")
+                .arg("mpegts")
+                .arg("-latency")
+                .arg((latency * 1000).to_string());
+        }
+        None => {
+            command.arg("-f").arg("flv");
+        }
+    }
+}
+
+/// Adds the built-in ffmpeg arguments used when no command template is configured.
+fn add_builtin_args(
+    command: &mut Command,
+    m3u8_url: String,
+    output: String,
+    proxy: Option<String>,
+    log_level: &str,
+    options: &FfmpegOptions,
+) {
+    let encoder = if options.copy {
+        None
+    } else {
+        let encoder = video_encoder(options);
+        if encoder.is_none() {
+            tracing::warn!(
+                "硬件编码 {} 不可用，回退到 -c copy",
+                options.hw_accel.as_deref().unwrap_or_default()
+            );
+        }
+        encoder
+    };
+    if let Some(proxy) = proxy {
+        command.arg("-http_proxy").arg(proxy);
+    }
+    if encoder == Some("h264_vaapi") {
+        command.arg("-vaapi_device").arg(VAAPI_DEVICE);
+    }
+    if options.http_multiple {
+        command.arg("-http_multiple").arg("1");
+    }
+    if options.re {
+        command.arg("-re");
+    }
+    // cache 8 seconds before output
+    command.arg("-i").arg(m3u8_url);
+    if let Some(encoder) = encoder {
+        let mut filters = Vec::new();
+        if let Some(ticker) = &options.ticker {
+            match ticker_filter(ticker) {
+                Ok(filter) => filters.push(filter),
+                Err(e) => tracing::error!("写入滚动字幕文件失败: {}", e),
+            }
+        }
+        if let Some(watermark) = &options.watermark {
+            match watermark_filter(watermark) {
+                Ok(filter) => filters.push(filter),
+                Err(e) => tracing::error!("写入水印文件失败: {}", e),
+            }
+        }
+        if encoder == "h264_vaapi" {
+            filters.push("format=nv12,hwupload".to_string());
+        }
+        if !filters.is_empty() {
+            command.arg("-vf").arg(filters.join(","));
+        }
+        command.arg("-c:v").arg(encoder);
+        if let Some(preset) = &options.preset {
+            command.arg("-preset").arg(preset);
+        }
+        if let Some(bitrate) = &options.video_bitrate {
+            command
+                .arg("-b:v")
+                .arg(bitrate)
+                .arg("-maxrate")
+                .arg(bitrate);
+            if let Some(bufsize) = &options.bufsize {
+                command.arg("-bufsize").arg(bufsize);
+            }
+        }
+        command.arg("-c:a").arg("aac");
+        if let Some(bitrate) = &options.audio_bitrate {
+            command.arg("-b:a").arg(bitrate);
+        }
+        match options.gop {
+            Some(gop) => {
+                command.arg("-g").arg(gop.to_string());
+            }
+            // One keyframe every 2 seconds whatever the source frame rate is
+            None => {
+                command
+                    .arg("-force_key_frames")
+                    .arg("expr:gte(t,n_forced*2)");
+            }
+        }
+        if let Some(keyint_min) = options.keyint_min {
+            command.arg("-keyint_min").arg(keyint_min.to_string());
+        }
+    } else {
+        if options.copy && options.ticker.is_some() {
+            tracing::warn!("滚动字幕需要转码，请将 FfmpegOptions.Copy 设为 false");
+        }
+        if options.copy && options.watermark.is_some() {
+            tracing::warn!("移动水印需要转码，请将 FfmpegOptions.Copy 设为 false");
+        }
+        command.arg("-c").arg("copy");
+    }
+    command
+        .arg("-fflags")
+        .arg("+genpts")
+        .arg("-max_delay")
+        .arg("8000000")
+        .arg("-analyzeduration")
+        .arg("8000000");
+    add_output_format(command, options.srt_latency);
+    command.arg(output);
+    // Each extra output gets its own encoder; ffmpeg decodes the input once and feeds them all
+    for target in &options.extra_rtmp_targets {
+        if target.video_bitrate.is_none() && target.height.is_none() {
+            command.arg("-c").arg("copy");
+        } else {
+            command.arg("-c:v").arg("libx264").arg("-c:a").arg("aac");
+            if let Some(bitrate) = &target.video_bitrate {
+                command.arg("-b:v").arg(bitrate);
+            }
+            if let Some(height) = target.height {
+                command.arg("-vf").arg(format!("scale=-2:{}", height));
+            }
+            command
+                .arg("-force_key_frames")
+                .arg("expr:gte(t,n_forced*2)");
+        }
+        command.arg("-f").arg("flv").arg(&target.url);
+    }
+    command.arg("-loglevel").arg(log_level).arg("-stats");
+}
+
+/// Url parts that identify the viewer or sign the request, masked before logging.
+const SENSITIVE_URL_KEYS: [&str; 6] = ["sig", "lsig", "signature", "token", "ip", "key"];
+
+/// Masked input url of the running ffmpeg, see [`current_input_url`].
+static CURRENT_INPUT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Returns the input url of the running ffmpeg with signatures and tokens masked.
+pub fn current_input_url() -> Option<String> {
+    CURRENT_INPUT.lock().unwrap().clone()
+}
+
+/// Masks sensitive query values (`?sig=...`) and path pairs (`/sig/...`, used by YouTube)
+/// so that the url can be shown without leaking the signature.
+pub fn mask_url(url: &str) -> String {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    let mut mask_next = false;
+    let mut masked: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        if mask_next {
+            masked.push("***");
+            mask_next = false;
+        } else {
+            mask_next = SENSITIVE_URL_KEYS.contains(&segment);
+            masked.push(segment);
+        }
+    }
+    let mut masked = masked.join("/");
+    if let Some(query) = query {
+        let pairs: Vec<String> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if SENSITIVE_URL_KEYS.contains(&key) => format!("{}=***", key),
+                _ => pair.to_string(),
+            })
+            .collect();
+        masked.push('?');
+        masked.push_str(&pairs.join("&"));
+    }
+    masked
+}
+
+/// The ffmpeg child spawned by [`ffmpeg`], kept so that [`stop_ffmpeg`] can kill it.
+static RUNNING_FFMPEG: Mutex<Option<Child>> = Mutex::new(None);
+
+/// Exponential delay between ffmpeg restarts: 2s after the first failure, then 4, 8,
+/// 16 ... capped at 60s. A run lasting 60s or more counts as stable and starts over.
+#[derive(Debug, Default)]
+pub struct RestartBackoff {
+    failures: u32,
+}
+
+impl RestartBackoff {
+    const INITIAL: Duration = Duration::from_secs(2);
+    const MAX: Duration = Duration::from_secs(60);
+    const STABLE_RUN: Duration = Duration::from_secs(60);
+
+    /// Returns how long to wait before restarting an ffmpeg that ran for `ran_for`.
+    pub fn next_delay(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= Self::STABLE_RUN {
+            self.failures = 0;
+        }
+        let delay = Self::INITIAL
+            .saturating_mul(1 << self.failures.min(16))
+            .min(Self::MAX);
+        self.failures += 1;
+        delay
+    }
+}
+
+/// Set by [`stop_ffmpeg`] so that [`ffmpeg`] does not go on with the source after a
+/// killed placeholder.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Kills the ffmpeg started by this process, making the blocked [`ffmpeg`] call return.
+/// Returns false when no ffmpeg is running.
+pub fn stop_ffmpeg() -> bool {
+    match RUNNING_FFMPEG.lock().unwrap().as_mut() {
+        Some(child) => {
+            STOP_REQUESTED.store(true, Ordering::Relaxed);
+            if let Err(e) = child.kill() {
+                tracing::error!("停止ffmpeg失败: {}", e);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// How long the RTMP server gets to accept a TCP connection before pushing to it.
+const RTMP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks that the push address is complete and that its server accepts connections,
+/// so that an empty or stale address is reported instead of ffmpeg failing over and
+/// over with an unclear error.
+fn check_rtmp_target(rtmp_url: &str, rtmp_key: &str) -> Result<(), String> {
+    if rtmp_url.trim().is_empty() || rtmp_key.trim().is_empty() {
+        return Err("推流地址无效（BiliRtmpUrl 或 BiliRtmpKey 为空），请重新开播获取".to_string());
+    }
+    let (scheme, rest) = rtmp_url
+        .split_once("://")
+        .ok_or_else(|| format!("推流地址无效: {}，请重新开播获取", rtmp_url))?;
+    let default_port = match scheme {
+        "rtmp" => 1935,
+        "rtmps" => 443,
+        // Other protocols are left to ffmpeg
+        _ => return Ok(()),
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, default_port)
+    };
+    let addrs = std::net::ToSocketAddrs::to_socket_addrs(&address)
+        .map_err(|e| format!("无法解析推流服务器 {}: {}", address, e))?;
+    for addr in addrs {
+        if std::net::TcpStream::connect_timeout(&addr, RTMP_CONNECT_TIMEOUT).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "无法连接推流服务器 {}，请检查网络或重新开播获取推流地址",
+        address
+    ))
+}
+
+/// Pushes `placeholder` to `output` for its `Seconds`, looping a clip shorter than that.
+/// The source is pulled right after, so the room goes from the placeholder to the
+/// source with only the RTMP reconnect in between. Returns false when the placeholder
+/// was stopped by [`stop_ffmpeg`] or `stop-live`, true otherwise, also when it failed.
+fn push_placeholder(
+    placeholder: &Placeholder,
+    output: &str,
+    platform: &str,
+    srt_latency: Option<u64>,
+) -> bool {
+    let is_image = Path::new(&placeholder.file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "png" | "jpg" | "jpeg" | "bmp" | "webp"
+            )
+        });
+    let seconds = placeholder.seconds.to_string();
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-loglevel", "error", "-re"]);
+    if is_image {
+        command.args(["-loop", "1"]);
+    } else {
+        command.args(["-stream_loop", "-1"]);
+    }
+    command
+        .args(["-i", &placeholder.file])
+        .args(["-f", "lavfi", "-i", "anullsrc=r=44100:cl=stereo"])
+        .args(["-map", "0:v:0", "-map", "1:a:0", "-t", &seconds])
+        .args([
+            "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+        ])
+        .args(["-r", "30", "-g", "60", "-c:a", "aac", "-b:a", "128k"]);
+    add_output_format(&mut command, srt_latency);
+    command.arg(output).stdin(Stdio::null());
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!("推送占位画面失败: {}", e);
+            return true;
+        }
+    };
+    // Lets `stop-live` from another process stop the placeholder as well
+    if let Err(e) = fs::write(format!("ffmpeg.lock-{}", platform), child.id().to_string()) {
+        tracing::error!("写入ffmpeg锁文件失败: {}", e);
+    }
+    tracing::info!("推送占位画面 {} 秒: {}", seconds, placeholder.file);
+    *RUNNING_FFMPEG.lock().unwrap() = Some(child);
+    // Polled instead of waited on, so that stop_ffmpeg can still reach the child
+    let status = loop {
+        let mut running = RUNNING_FFMPEG.lock().unwrap();
+        let Some(child) = running.as_mut() else {
+            return true;
+        };
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                running.take();
+                break Ok(status);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                running.take();
+                break Err(e);
+            }
+        }
+        drop(running);
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    match status {
+        // A kill from another process shows up as an exit by signal
+        Ok(status) if status.code().is_none() => false,
+        Ok(status) => {
+            if !status.success() {
+                tracing::warn!("占位画面推流异常退出: {}", status);
+            }
+            !STOP_REQUESTED.load(Ordering::Relaxed)
+        }
+        Err(e) => {
+            tracing::error!("等待占位画面推流退出失败: {}", e);
+            !STOP_REQUESTED.load(Ordering::Relaxed)
+        }
+    }
+}
+
 /// Executes the ffmpeg command with the provided parameters.
 /// Prevents multiple instances from running simultaneously using platform-specific lock files.
 pub fn ffmpeg(
@@ -38,12 +1007,17 @@ pub fn ffmpeg(
     proxy: Option<String>,
     log_level: &str,
     platform: &str,
+    options: &FfmpegOptions,
 ) {
     // Check if any ffmpeg is already running
     if is_any_ffmpeg_running() {
         tracing::info!("一个ffmpeg实例已经在运行。跳过新实例。");
         return;
     }
+    if let Err(e) = check_rtmp_target(&rtmp_url, &rtmp_key) {
+        tracing::error!("{}", e);
+        return;
+    }
 
     // Create the lock file for the specified platform
     if let Err(e) = create_ffmpeg_lock(platform) {
@@ -51,44 +1025,114 @@ pub fn ffmpeg(
         return;
     }
 
+    let input = mask_url(&m3u8_url);
+    tracing::info!("ffmpeg输入: {}", input);
+    *CURRENT_INPUT.lock().unwrap() = Some(input);
+
     let cmd = format!("{}{}", rtmp_url, rtmp_key);
+    STOP_REQUESTED.store(false, Ordering::Relaxed);
+    if let Some(placeholder) = &options.placeholder {
+        if !push_placeholder(placeholder, &cmd, platform, options.srt_latency) {
+            tracing::info!("占位画面推流被停止，不再拉取源");
+            *CURRENT_INPUT.lock().unwrap() = None;
+            if let Err(e) = remove_ffmpeg_lock(platform) {
+                tracing::error!("删除ffmpeg锁文件失败: {}", e);
+            }
+            return;
+        }
+    }
     let mut command = Command::new("ffmpeg");
-
-    if let Some(proxy) = proxy {
-        command.arg("-http_proxy").arg(proxy);
+    if options.command_template.is_empty() {
+        add_builtin_args(&mut command, m3u8_url, cmd, proxy, log_level, options);
+    } else {
+        command.args(render_command_template(
+            &options.command_template,
+            &m3u8_url,
+            &cmd,
+            proxy.as_deref(),
+        ));
     }
-    // cache 8 seconds before output
-    command
-        .arg("-i")
-        .arg(m3u8_url)
-        .arg("-c")
-        .arg("copy")
-        .arg("-fflags")
-        .arg("+genpts")
-        .arg("-max_delay")
-        .arg("8000000")
-        .arg("-analyzeduration")
-        .arg("8000000")
-        .arg("-f")
-        .arg("flv")
-        .arg(cmd)
-        .arg("-loglevel")
-        .arg(log_level)
-        .arg("-stats");
-
-    match command.status() {
-        Ok(status) => {
-            if let Some(code) = status.code() {
-                tracing::info!("ffmpeg退出状态码: {}", code);
-            } else {
-                tracing::info!("ffmpeg被信号终止");
+
+    match command.stderr(Stdio::piped()).spawn() {
+        Ok(mut child) => {
+            let stderr = child.stderr.take();
+            LAST_SPEED.store(0, Ordering::Relaxed);
+            let _ = fs::remove_file(format!("ffmpeg.speed-{}", platform));
+            // Lets `stop-live` from another process find this ffmpeg
+            if let Err(e) = fs::write(format!("ffmpeg.lock-{}", platform), child.id().to_string()) {
+                tracing::error!("写入ffmpeg锁文件失败: {}", e);
+            }
+            *RUNNING_FFMPEG.lock().unwrap() = Some(child);
+            if let Some(stderr) = stderr {
+                let desync_count = watch_ffmpeg_stderr(stderr, platform);
+                if desync_count > 0 {
+                    tracing::info!("本次推流共检测到 {} 次时间戳异常", desync_count);
+                }
+            }
+            if let Some(mut child) = RUNNING_FFMPEG.lock().unwrap().take() {
+                match child.wait() {
+                    Ok(status) => {
+                        if let Some(code) = status.code() {
+                            tracing::info!("ffmpeg退出状态码: {}", code);
+                        } else {
+                            tracing::info!("ffmpeg被信号终止");
+                        }
+                    }
+                    Err(e) => tracing::error!("等待ffmpeg退出失败: {}", e),
+                }
             }
         }
         Err(e) => tracing::error!("执行ffmpeg失败: {}", e),
     }
 
+    *CURRENT_INPUT.lock().unwrap() = None;
+
     // Remove the lock file after ffmpeg finishes
     if let Err(e) = remove_ffmpeg_lock(platform) {
         tracing::error!("删除ffmpeg锁文件失败: {}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_command_template_substitutes_placeholders() {
+        let template: Vec<String> = ["-http_proxy", "{proxy}", "-i", "{input}", "{output}"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            render_command_template(&template, "in", "out", Some("http://proxy")),
+            ["-http_proxy", "http://proxy", "-i", "in", "out"]
+        );
+        assert_eq!(
+            render_command_template(&template, "in", "out", None),
+            ["-i", "in", "out"]
+        );
+    }
+
+    #[test]
+    fn mask_url_masks_query_and_path_keys() {
+        assert_eq!(
+            mask_url("https://host/api/sig/abc123/ip/1.2.3.4/index.m3u8?token=xyz&expire=10"),
+            "https://host/api/sig/***/ip/***/index.m3u8?token=***&expire=10"
+        );
+        assert_eq!(mask_url("rtmp://host/live"), "rtmp://host/live");
+    }
+
+    #[test]
+    fn restart_backoff_doubles_and_resets_after_stable_run() {
+        let mut backoff = RestartBackoff::default();
+        let short = Duration::from_secs(1);
+        let delays: Vec<u64> = (0..7)
+            .map(|_| backoff.next_delay(short).as_secs())
+            .collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(
+            backoff.next_delay(Duration::from_secs(60)),
+            Duration::from_secs(2)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use crate::config::Config;
+use async_trait::async_trait;
+use std::error::Error;
+use std::fs;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Player names checked against this file, one word per line.
+const INVALID_WORDS_FILE: &str = "invalid_words.txt";
+
+/// Bilibili areas with a monitor: League of Legends and Valorant.
+pub const GAME_MONITOR_AREAS: [u64; 2] = [86, 329];
+
+/// Valorant match lookups are rate limited, so they are not polled faster than this.
+const VALORANT_MIN_INTERVAL: u64 = 60;
+
+/// A puuid from puuid.txt and the region written after it, if any.
+pub type RiotPlayer = (String, Option<String>);
+
+/// Looks up the players of the game the monitored streamer is in.
+#[async_trait]
+pub trait GameMonitor: Send + Sync {
+    /// Riot ids (`Name#TAG`) of the players, empty when the streamer is not in a game.
+    async fn player_ids(&self) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Seconds between two lookups, given `LolMonitorInterval`.
+    fn poll_interval(&self, configured: u64) -> u64 {
+        configured
+    }
+}
+
+/// Current League of Legends game, through the spectator API in get_lol_id.py.
+pub struct LolMonitor {
+    api_key: String,
+    puuid: String,
+    region: String,
+}
+
+#[async_trait]
+impl GameMonitor for LolMonitor {
+    async fn player_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let output = Command::new("python3")
+            .arg("get_lol_id.py")
+            .arg(&self.api_key)
+            .arg(&self.puuid)
+            .arg(&self.region)
+            .output()?;
+        let ids = String::from_utf8(output.stdout)?;
+        // The script prints the error instead of a list when there is no game
+        if !ids.trim().starts_with('[') {
+            return Ok(Vec::new());
+        }
+        Ok(parse_riot_ids(&ids))
+    }
+}
+
+/// Parses the Python list printed by get_lol_id.py, e.g. `['Kamito#8595', 'Name#JP1']`.
+fn parse_riot_ids(output: &str) -> Vec<String> {
+    output
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(", ")
+        .map(|id| {
+            id.trim()
+                .trim_matches(|c| c == '\'' || c == '"')
+                .to_string()
+        })
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Valorant has no spectator API, so this reports the players of the latest finished
+/// match of the streamer. The match already finished when monitoring started is
+/// reported as no game, so the live is never stopped over the previous match.
+pub struct ValorantMonitor {
+    api_key: String,
+    puuid: String,
+    region: String,
+    client: reqwest::Client,
+    /// Latest match id at the first lookup, None until then.
+    first_match: Mutex<Option<String>>,
+}
+
+#[async_trait]
+impl GameMonitor for ValorantMonitor {
+    async fn player_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let base = format!("https://{}.api.riotgames.com/val/match/v1", self.region);
+        let matches: serde_json::Value = self
+            .client
+            .get(format!("{}/matchlists/by-puuid/{}", base, self.puuid))
+            .header("X-Riot-Token", &self.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let Some(match_id) = matches["history"][0]["matchId"].as_str() else {
+            return Ok(Vec::new());
+        };
+        {
+            let mut first_match = self.first_match.lock().unwrap_or_else(|e| e.into_inner());
+            if first_match.get_or_insert_with(|| match_id.to_string()) == match_id {
+                return Ok(Vec::new());
+            }
+        }
+        let game: serde_json::Value = self
+            .client
+            .get(format!("{}/matches/{}", base, match_id))
+            .header("X-Riot-Token", &self.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(game["players"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|player| {
+                let name = player["gameName"].as_str()?;
+                let tag = player["tagLine"].as_str()?;
+                Some(format!("{}#{}", name, tag))
+            })
+            .collect())
+    }
+
+    fn poll_interval(&self, configured: u64) -> u64 {
+        configured.max(VALORANT_MIN_INTERVAL)
+    }
+}
+
+/// Picks the monitor for the Bilibili area: LoL (86) or Valorant (329). `player` is
+/// the puuid from puuid.txt, its region only applies to LoL. Returns `None` for
+/// other areas or without `RiotApiKey`.
+pub fn select_game_monitor(
+    cfg: &Config,
+    player: Option<RiotPlayer>,
+) -> Option<Box<dyn GameMonitor>> {
+    let (puuid, region) = player?;
+    let api_key = cfg.riot_api_key.clone().filter(|key| !key.is_empty())?;
+    match cfg.bililive.area_v2 {
+        86 => Some(Box::new(LolMonitor {
+            api_key,
+            puuid,
+            region: region
+                .unwrap_or_else(|| cfg.lol_region.clone())
+                .to_lowercase(),
+        })),
+        329 => Some(Box::new(ValorantMonitor {
+            api_key,
+            puuid,
+            region: cfg.valorant_region.to_lowercase(),
+            client: reqwest::Client::new(),
+            first_match: Mutex::new(None),
+        })),
+        _ => None,
+    }
+}
+
+/// Returns the first word of invalid_words.txt found in the player ids.
+pub fn find_invalid_word(ids: &[String]) -> Option<String> {
+    let invalid_words = fs::read_to_string(INVALID_WORDS_FILE).ok()?;
+    invalid_words
+        .lines()
+        .filter(|word| !word.trim().is_empty())
+        .find(|word| ids.iter().any(|id| id.contains(word)))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_riot_ids_reads_python_list() {
+        assert_eq!(
+            parse_riot_ids("['Kamito#8595', \"Name#JP1\"]\n"),
+            ["Kamito#8595", "Name#JP1"]
+        );
+        assert!(parse_riot_ids("[]").is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use super::danmaku::{danmaku_connected, danmaku_reconnect_count, is_any_danmaku_running};
+use super::ffmpeg::{current_input_url, current_speed, is_any_ffmpeg_running};
+use crate::config::Config;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::fs;
+use std::sync::Mutex;
+
+/// What the last checks of this process found, written out by [`write_health`].
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Health {
+    pub ffmpeg_running: bool,
+    pub ffmpeg_speed: Option<f32>,
+    /// Input url of the running ffmpeg, with signatures and tokens masked.
+    pub input_url: Option<String>,
+    pub danmaku_running: bool,
+    pub danmaku_connected: bool,
+    pub danmaku_reconnect_count: u32,
+    pub source_live: bool,
+    /// Probed when the rebroadcast starts, e.g. `1080p60 6.0Mbps`.
+    pub source_quality: Option<String>,
+    pub last_source_check: Option<DateTime<Local>>,
+    pub bili_live: Option<bool>,
+    /// When `bili_live` was checked, to tell how stale it is.
+    pub last_bili_check: Option<DateTime<Local>>,
+}
+
+static HEALTH: Mutex<Option<Health>> = Mutex::new(None);
+
+fn update_health(cfg: &Config, update: impl FnOnce(&mut Health)) {
+    let health = {
+        let mut health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+        let health = health.get_or_insert_with(Health::default);
+        update(health);
+        health.ffmpeg_running = is_any_ffmpeg_running();
+        health.ffmpeg_speed = current_speed();
+        health.input_url = current_input_url();
+        health.danmaku_running = is_any_danmaku_running();
+        health.danmaku_connected = danmaku_connected();
+        health.danmaku_reconnect_count = danmaku_reconnect_count();
+        health.clone()
+    };
+    write_health(cfg, &health);
+}
+
+/// Records a successful check of the source platform.
+pub fn record_source_status(cfg: &Config, is_live: bool) {
+    update_health(cfg, |health| {
+        health.source_live = is_live;
+        health.last_source_check = Some(Local::now());
+    });
+}
+
+/// Records the quality of the source being rebroadcast.
+pub fn record_source_quality(cfg: &Config, quality: Option<String>) {
+    update_health(cfg, |health| health.source_quality = quality);
+}
+
+/// Records the live status of the Bilibili room.
+pub fn record_bili_status(cfg: &Config, is_live: bool) {
+    update_health(cfg, |health| {
+        health.bili_live = Some(is_live);
+        health.last_bili_check = Some(Local::now());
+    });
+}
+
+/// Rewrites the health files with the current ffmpeg and danmaku state.
+pub fn refresh_health(cfg: &Config) {
+    update_health(cfg, |_| {});
+}
+
+/// Renders the health as Prometheus text format, for node_exporter's textfile collector.
+fn prometheus_metrics(platform: &str, health: &Health) -> String {
+    let gauges = [
+        (
+            "bilistream_ffmpeg_running",
+            "Whether ffmpeg is pushing to Bilibili",
+            health.ffmpeg_running as u8 as f64,
+        ),
+        (
+            "bilistream_ffmpeg_speed",
+            "Last speed reported by ffmpeg, 0 when not streaming",
+            health.ffmpeg_speed.unwrap_or(0.0) as f64,
+        ),
+        (
+            "bilistream_danmaku_running",
+            "Whether the danmaku command reader is running",
+            health.danmaku_running as u8 as f64,
+        ),
+        (
+            "bilistream_danmaku_connected",
+            "Whether live-danmaku-cli is connected in this process",
+            health.danmaku_connected as u8 as f64,
+        ),
+        (
+            "bilistream_danmaku_reconnects",
+            "Restarts of live-danmaku-cli since bilistream started",
+            health.danmaku_reconnect_count as f64,
+        ),
+        (
+            "bilistream_is_live",
+            "Whether the source channel is live",
+            health.source_live as u8 as f64,
+        ),
+        (
+            "bilistream_bili_live",
+            "Whether the Bilibili room is live",
+            health.bili_live.unwrap_or(false) as u8 as f64,
+        ),
+        (
+            "bilistream_last_check_timestamp_seconds",
+            "Unix time of the last successful source check",
+            health
+                .last_source_check
+                .map_or(0.0, |time| time.timestamp() as f64),
+        ),
+        (
+            "bilistream_last_bili_check_timestamp_seconds",
+            "Unix time of the last Bilibili room check",
+            health
+                .last_bili_check
+                .map_or(0.0, |time| time.timestamp() as f64),
+        ),
+    ];
+    gauges
+        .iter()
+        .map(|(name, help, value)| {
+            format!(
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name}{{platform=\"{platform}\"}} {value}\n"
+            )
+        })
+        .collect()
+}
+
+/// Writes `content` through a temporary file so readers never see half a file.
+fn write_atomic(path: &str, content: &str) {
+    let tmp = format!("{}.tmp", path);
+    if let Err(e) = fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, path)) {
+        tracing::error!("写入 {} 失败: {}", path, e);
+    }
+}
+
+/// Writes the JSON health file and the Prometheus metrics file when configured.
+/// Does nothing when neither `HealthJsonPath` nor `MetricsPath` is set.
+fn write_health(cfg: &Config, health: &Health) {
+    if let Some(path) = cfg.health_json_path.as_deref().filter(|p| !p.is_empty()) {
+        match serde_json::to_string_pretty(health) {
+            Ok(json) => write_atomic(path, &json),
+            Err(e) => tracing::error!("序列化健康状态失败: {}", e),
+        }
+    }
+    if let Some(path) = cfg.metrics_path.as_deref().filter(|p| !p.is_empty()) {
+        let platform = if cfg.platform == "Twitch" { "TW" } else { "YT" };
+        write_atomic(path, &prometheus_metrics(platform, health));
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File the rebroadcast history is stored in, shared by the YT and TW processes.
+const HISTORY_FILE: &str = "history.json";
+/// Held while one process rewrites the history file, so YT and TW do not overwrite
+/// each other's records.
+const HISTORY_LOCK_FILE: &str = "history.json.lock";
+/// A lock file older than this was left behind by a crashed process.
+const HISTORY_LOCK_STALE: Duration = Duration::from_secs(10);
+
+/// Serializes read-modify-write cycles on the history file within this process.
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Holds both the in-process lock and the lock file until dropped.
+struct HistoryGuard {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Drop for HistoryGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(HISTORY_LOCK_FILE);
+    }
+}
+
+fn lock_history() -> HistoryGuard {
+    let guard = HISTORY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let started = Instant::now();
+    loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(HISTORY_LOCK_FILE)
+        {
+            Ok(_) => break,
+            Err(_) if started.elapsed() >= HISTORY_LOCK_STALE => {
+                tracing::warn!("{} 长时间未释放，强制使用", HISTORY_LOCK_FILE);
+                break;
+            }
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+    HistoryGuard { _guard: guard }
+}
+
+/// One rebroadcast session.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LiveRecord {
+    pub channel_name: String,
+    pub platform: String,
+    pub area_id: u64,
+    pub area_name: String,
+    pub start: DateTime<Local>,
+    pub end: Option<DateTime<Local>>,
+    pub restarts: u32,
+}
+
+impl LiveRecord {
+    /// Length of the session, counting an unfinished one up to now.
+    pub fn duration(&self) -> chrono::Duration {
+        self.end.unwrap_or_else(Local::now) - self.start
+    }
+}
+
+/// Loads all records, oldest first. A missing or broken file yields no records.
+pub fn load_history() -> Vec<LiveRecord> {
+    fs::read_to_string(HISTORY_FILE)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_history(records: &[LiveRecord]) -> Result<(), Box<dyn Error>> {
+    fs::write(HISTORY_FILE, serde_json::to_string_pretty(records)?)?;
+    Ok(())
+}
+
+/// Appends a new, still running session.
+pub fn record_live_start(
+    channel_name: &str,
+    platform: &str,
+    area_id: u64,
+    area_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let _guard = lock_history();
+    let mut records = load_history();
+    records.push(LiveRecord {
+        channel_name: channel_name.to_string(),
+        platform: platform.to_string(),
+        area_id,
+        area_name: area_name.to_string(),
+        start: Local::now(),
+        end: None,
+        restarts: 0,
+    });
+    save_history(&records)
+}
+
+/// Closes the latest running session of `platform`.
+pub fn record_live_end(platform: &str, restarts: u32) -> Result<(), Box<dyn Error>> {
+    let _guard = lock_history();
+    let mut records = load_history();
+    if let Some(record) = records
+        .iter_mut()
+        .rev()
+        .find(|record| record.platform == platform && record.end.is_none())
+    {
+        record.end = Some(Local::now());
+        record.restarts = restarts;
+    }
+    save_history(&records)
+}
+
+/// The latest `count` records, newest first.
+pub fn recent_history(count: usize) -> Vec<LiveRecord> {
+    load_history().into_iter().rev().take(count).collect()
+}
+
+/// Total rebroadcast time of the sessions started in the last 7 days.
+pub fn weekly_duration(records: &[LiveRecord]) -> chrono::Duration {
+    let week_ago = Local::now() - chrono::Duration::days(7);
+    records
+        .iter()
+        .filter(|record| record.start > week_ago)
+        .map(LiveRecord::duration)
+        .fold(chrono::Duration::zero(), |total, duration| total + duration)
+}
+
+/// Quotes a CSV field when it contains a separator, quote or newline.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Renders all records as CSV with a header row. Times are in the local timezone.
+pub fn history_to_csv(records: &[LiveRecord]) -> String {
+    let mut csv = String::from("频道,平台,分区,开始,结束,时长,重启次数\n");
+    for record in records {
+        let seconds = record.duration().num_seconds().max(0);
+        let row = [
+            csv_field(&record.channel_name),
+            csv_field(&record.platform),
+            csv_field(&record.area_name),
+            record.start.format("%Y-%m-%d %H:%M:%S").to_string(),
+            record
+                .end
+                .map(|end| end.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds % 3600 / 60,
+                seconds % 60
+            ),
+            record.restarts.to_string(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
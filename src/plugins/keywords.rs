@@ -0,0 +1,102 @@
+use regex::Regex;
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// One keyword per line, `re:` lines are regexes; `#` starts a comment line.
+const BANNED_KEYWORDS_FILE: &str = "banned_keywords.txt";
+/// Keywords used when `banned_keywords.txt` does not exist.
+const DEFAULT_BANNED_KEYWORDS: &str = include_str!("../../banned_keywords.txt");
+
+#[derive(Debug, Clone)]
+pub enum BannedKeyword {
+    /// Lowercased substring.
+    Plain(String),
+    Regex(Regex),
+}
+
+impl BannedKeyword {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            BannedKeyword::Plain(keyword) => text.to_lowercase().contains(keyword),
+            BannedKeyword::Regex(re) => re.is_match(text),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            BannedKeyword::Plain(keyword) => keyword,
+            BannedKeyword::Regex(re) => re.as_str(),
+        }
+    }
+}
+
+fn parse_banned_keywords(content: &str) -> Vec<BannedKeyword> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match line.strip_prefix("re:") {
+            Some(pattern) => match Regex::new(&format!("(?i){}", pattern)) {
+                Ok(re) => Some(BannedKeyword::Regex(re)),
+                Err(e) => {
+                    tracing::error!("{} 中的正则 {} 无效: {}", BANNED_KEYWORDS_FILE, pattern, e);
+                    None
+                }
+            },
+            None => Some(BannedKeyword::Plain(line.to_lowercase())),
+        })
+        .collect()
+}
+
+/// Keywords of the last read and the mtime of the file they were read from.
+static BANNED_KEYWORDS: Mutex<Option<(Option<SystemTime>, Vec<BannedKeyword>)>> = Mutex::new(None);
+
+/// Returns the banned keywords, reading `banned_keywords.txt` again whenever its mtime
+/// changed so that edits apply without a restart.
+pub fn load_banned_keywords() -> Vec<BannedKeyword> {
+    let modified = fs::metadata(BANNED_KEYWORDS_FILE)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let mut cache = BANNED_KEYWORDS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_modified, keywords)) = cache.as_ref() {
+        if *cached_modified == modified {
+            return keywords.clone();
+        }
+    }
+    let keywords = match modified {
+        Some(_) => match fs::read_to_string(BANNED_KEYWORDS_FILE) {
+            Ok(content) => parse_banned_keywords(&content),
+            Err(e) => {
+                tracing::error!("读取{}失败: {}", BANNED_KEYWORDS_FILE, e);
+                parse_banned_keywords(DEFAULT_BANNED_KEYWORDS)
+            }
+        },
+        None => parse_banned_keywords(DEFAULT_BANNED_KEYWORDS),
+    };
+    *cache = Some((modified, keywords.clone()));
+    keywords
+}
+
+/// Returns the first banned keyword found in `text`.
+pub fn find_banned_keyword(text: &str) -> Option<BannedKeyword> {
+    load_banned_keywords()
+        .into_iter()
+        .find(|keyword| keyword.matches(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_banned_keywords_skips_comments_and_bad_regexes() {
+        let keywords =
+            parse_banned_keywords("# comment\n\n  Karaoke \nre:^watch.?along\nre:(unclosed\n");
+        let names: Vec<&str> = keywords.iter().map(BannedKeyword::as_str).collect();
+        assert_eq!(names, ["karaoke", "(?i)^watch.?along"]);
+        assert!(keywords[0].matches("KARAOKE night"));
+        assert!(keywords[1].matches("Watch-along stream"));
+        assert!(!keywords[1].matches("let's watchalong"));
+    }
+}
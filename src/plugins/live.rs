@@ -6,9 +6,15 @@ use reqwest_middleware::ClientBuilder;
 use reqwest_retry::policies::ExponentialBackoff;
 use reqwest_retry::RetryTransientMiddleware;
 use std::error::Error;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
 #[async_trait]
 pub trait Live {
+    /// Returns `(is_live, m3u8_url, title, scheduled_start, is_members_only)`.
+    /// `is_members_only` is set when the channel is live but the live cannot be
+    /// rebroadcast because it is for members only.
     async fn get_status(
         &self,
     ) -> Result<
@@ -17,6 +23,7 @@ pub trait Live {
             Option<String>,
             Option<String>,
             Option<DateTime<Local>>,
+            bool,
         ),
         Box<dyn Error>,
     >;
@@ -36,17 +43,59 @@ pub async fn select_live(cfg: Config) -> Result<Box<dyn Live>, Box<dyn Error>> {
         .build();
     match cfg.platform.as_str() {
         "Youtube" => Ok(Box::new(Youtube::new(
-            &cfg.youtube.channel_name.as_str(),
-            &cfg.youtube.channel_id.as_str(),
+            cfg.youtube.channel_name.as_str(),
+            cfg.youtube.channel_id.as_str(),
             cfg.proxy,
         ))),
 
         "Twitch" => Ok(Box::new(Twitch::new(
-            &cfg.twitch.channel_id.as_str(),
+            cfg.twitch.channel_id.as_str(),
             cfg.twitch.oauth_token,
             client.clone(),
             cfg.twitch.proxy_region,
+            cfg.twitch.disable_ads,
+            cfg.twitch.allow_rerun,
         ))),
         _ => Err("不支持的平台".into()),
     }
 }
+
+/// Candidate channels checked at the same time, to bound parallel yt-dlp/streamlink runs.
+const MAX_CONCURRENT_CHECKS: usize = 3;
+
+/// Checks every candidate channel (see `Config::candidate_channels`) concurrently and
+/// returns the config switched to the live one with the highest priority. Returns `None`
+/// when none of them is live or only `ChannelId` is configured.
+pub async fn select_live_channel(cfg: &Config) -> Option<Config> {
+    let candidates = cfg.candidate_channels();
+    if candidates.len() < 2 {
+        return None;
+    }
+    // yt-dlp and streamlink block, so each check gets a blocking thread of its own
+    let limit = Arc::new(Semaphore::new(MAX_CONCURRENT_CHECKS));
+    let checks: Vec<_> = candidates
+        .iter()
+        .map(|channel| {
+            let channel_cfg = cfg.with_channel(channel);
+            let limit = limit.clone();
+            let handle = Handle::current();
+            tokio::task::spawn_blocking(move || {
+                handle.block_on(async move {
+                    let _permit = limit.acquire_owned().await.ok()?;
+                    let live = select_live(channel_cfg.clone()).await.ok()?;
+                    match live.get_status().await {
+                        Ok((true, _, _, _, _)) => Some(channel_cfg),
+                        _ => None,
+                    }
+                })
+            })
+        })
+        .collect();
+    // Results are taken in priority order, the first live channel wins
+    for check in checks {
+        if let Ok(Some(channel_cfg)) = check.await {
+            return Some(channel_cfg);
+        }
+    }
+    None
+}
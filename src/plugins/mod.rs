@@ -1,13 +1,33 @@
+pub mod area;
 pub mod bilibili;
 pub mod danmaku;
+pub mod diagnose;
+pub mod doctor;
 pub mod ffmpeg;
+pub mod game_monitor;
+pub mod health;
+pub mod history;
+pub mod keywords;
 pub mod live;
+pub mod notify;
+pub mod overlay;
+pub mod stage;
 pub mod twitch;
 pub mod youtube;
 // Re-export commonly used items
+pub use area::*;
 pub use bilibili::*;
 pub use danmaku::*;
+pub use diagnose::*;
+pub use doctor::*;
 pub use ffmpeg::*;
+pub use game_monitor::*;
+pub use health::*;
+pub use history::*;
+pub use keywords::*;
 pub use live::*;
+pub use notify::*;
+pub use overlay::*;
+pub use stage::*;
 pub use twitch::*;
 pub use youtube::*;
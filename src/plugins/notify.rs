@@ -0,0 +1,148 @@
+use crate::config::Config;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Repeats of an alert within this window are only counted, and the count is sent
+/// with the next alert after the window or with the recovery notice.
+const ALERT_WINDOW: Duration = Duration::from_secs(600);
+
+struct AlertState {
+    kind: &'static str,
+    sent_at: Instant,
+    // Alerts since the last one sent, which are not sent yet
+    suppressed: u32,
+    total: u32,
+}
+
+static ALERTS: Mutex<Vec<AlertState>> = Mutex::new(Vec::new());
+
+/// Escapes the characters Telegram's legacy Markdown treats as markup, for names in messages.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '_' | '*' | '`' | '[') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Sends `message` (Telegram Markdown) to the configured Telegram chat.
+/// Does nothing without `TelegramBotToken` and `TelegramChatId`; failures are only logged.
+pub async fn notify(cfg: &Config, message: &str) {
+    let (Some(token), Some(chat_id)) = (&cfg.telegram_bot_token, &cfg.telegram_chat_id) else {
+        return;
+    };
+    if let Err(e) = send_telegram(token, chat_id, message, cfg.proxy.as_deref()).await {
+        tracing::error!("Telegram通知发送失败: {}", e);
+    }
+}
+
+/// Counts an alert of `kind` and returns the message to send for it, or `None` while an
+/// alert of the same kind was sent less than ten minutes ago. Used for both Telegram
+/// and danmaku alerts, which should use different kinds.
+pub fn aggregate_alert(kind: &'static str, message: &str) -> Option<String> {
+    let mut alerts = ALERTS.lock().unwrap();
+    let Some(state) = alerts.iter_mut().find(|state| state.kind == kind) else {
+        alerts.push(AlertState {
+            kind,
+            sent_at: Instant::now(),
+            suppressed: 0,
+            total: 1,
+        });
+        return Some(message.to_string());
+    };
+    state.total += 1;
+    if state.sent_at.elapsed() < ALERT_WINDOW {
+        state.suppressed += 1;
+        return None;
+    }
+    let count = state.suppressed + 1;
+    state.sent_at = Instant::now();
+    state.suppressed = 0;
+    Some(if count > 1 {
+        format!("{}（{} 次）", message, count)
+    } else {
+        message.to_string()
+    })
+}
+
+/// Ends the alert of `kind` and returns the recovery message with the number of
+/// alerts, or `None` when there was no alert of that kind.
+pub fn resolve_alert(kind: &'static str, message: &str) -> Option<String> {
+    let mut alerts = ALERTS.lock().unwrap();
+    let index = alerts.iter().position(|state| state.kind == kind)?;
+    let state = alerts.remove(index);
+    Some(format!("{}（期间共告警 {} 次）", message, state.total))
+}
+
+/// Forgets all alerts, e.g. when a rebroadcast is over.
+pub fn clear_alerts() {
+    ALERTS.lock().unwrap().clear();
+}
+
+/// [`notify`] through [`aggregate_alert`], for alerts that repeat while a problem lasts.
+pub async fn notify_alert(cfg: &Config, kind: &'static str, message: &str) {
+    if let Some(message) = aggregate_alert(kind, message) {
+        notify(cfg, &message).await;
+    }
+}
+
+/// Sends the recovery notice of an alert sent with [`notify_alert`].
+pub async fn notify_recovered(cfg: &Config, kind: &'static str, message: &str) {
+    if let Some(message) = resolve_alert(kind, message) {
+        notify(cfg, &message).await;
+    }
+}
+
+async fn send_telegram(
+    token: &str,
+    chat_id: &str,
+    message: &str,
+    proxy: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+    // api.telegram.org is often unreachable without the proxy
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let res: serde_json::Value = builder
+        .build()?
+        .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+        .json(&json!({
+            "chat_id": chat_id,
+            "text": message,
+            "parse_mode": "Markdown",
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if res["ok"] != true {
+        return Err(format!("{}", res["description"]).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_are_aggregated_until_resolved() {
+        assert_eq!(resolve_alert("test-alert", "恢复"), None);
+        assert_eq!(
+            aggregate_alert("test-alert", "断流"),
+            Some("断流".to_string())
+        );
+        assert_eq!(aggregate_alert("test-alert", "断流"), None);
+        assert_eq!(aggregate_alert("test-alert", "断流"), None);
+        assert_eq!(
+            resolve_alert("test-alert", "恢复"),
+            Some("恢复（期间共告警 3 次）".to_string())
+        );
+        assert_eq!(resolve_alert("test-alert", "恢复"), None);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::config::Config;
+use std::fs;
+
+/// Status shown in the OBS overlay files.
+pub struct OverlayStatus<'a> {
+    pub channel_name: Option<&'a str>,
+    pub platform: &'a str,
+    pub area_name: Option<&'a str>,
+    pub streaming: bool,
+}
+
+impl OverlayStatus<'_> {
+    fn lines(&self) -> Vec<String> {
+        match self.channel_name {
+            Some(channel_name) if self.streaming => vec![
+                format!("当前转播：{}", channel_name),
+                format!("平台：{}", self.platform),
+                format!("分区：{}", self.area_name.unwrap_or("未知")),
+                "状态：推流中".to_string(),
+            ],
+            _ => vec!["当前未转播".to_string()],
+        }
+    }
+}
+
+/// Escapes the characters that are special in HTML text.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the status to the txt/html files configured for OBS text or browser sources.
+/// Does nothing when neither `OverlayTxtPath` nor `OverlayHtmlPath` is set.
+pub fn write_overlay(cfg: &Config, status: &OverlayStatus) {
+    let lines = status.lines();
+    if let Some(path) = cfg.overlay_txt_path.as_deref().filter(|p| !p.is_empty()) {
+        if let Err(e) = fs::write(path, lines.join("\n")) {
+            tracing::error!("写入OBS文本文件 {} 失败: {}", path, e);
+        }
+    }
+    if let Some(path) = cfg.overlay_html_path.as_deref().filter(|p| !p.is_empty()) {
+        let body = lines
+            .iter()
+            .map(|line| format!("    <div>{}</div>", escape_html(line)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        // Refresh every 5 seconds so the browser source picks up changes
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n    <meta charset=\"utf-8\">\n    <meta http-equiv=\"refresh\" content=\"5\">\n    <style>body {{ color: white; font-size: 28px; text-shadow: 2px 2px 4px black; }}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+            body
+        );
+        if let Err(e) = fs::write(path, html) {
+            tracing::error!("写入OBS网页文件 {} 失败: {}", path, e);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The step `run_bilistream` is currently at, kept to tell where it got stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Starting,
+    CheckingYoutube,
+    CheckingTwitch,
+    CheckingBili,
+    /// Checking the area and whether someone else is already live in the room.
+    CheckingConflict,
+    StartingLive,
+    Streaming,
+    Waiting,
+}
+
+impl Stage {
+    const ALL: [Stage; 8] = [
+        Stage::Starting,
+        Stage::CheckingYoutube,
+        Stage::CheckingTwitch,
+        Stage::CheckingBili,
+        Stage::CheckingConflict,
+        Stage::StartingLive,
+        Stage::Streaming,
+        Stage::Waiting,
+    ];
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Stage::Starting => "启动中",
+            Stage::CheckingYoutube => "检测YT",
+            Stage::CheckingTwitch => "检测TW",
+            Stage::CheckingBili => "检测B站",
+            Stage::CheckingConflict => "撞车判断",
+            Stage::StartingLive => "开播",
+            Stage::Streaming => "推流中",
+            Stage::Waiting => "等待",
+        }
+    }
+}
+
+static CURRENT_STAGE: AtomicU8 = AtomicU8::new(0);
+
+/// Moves to `stage`, logging the change at debug level.
+pub fn set_stage(stage: Stage) {
+    let previous = CURRENT_STAGE.swap(stage as u8, Ordering::Relaxed);
+    if previous != stage as u8 {
+        tracing::debug!("阶段: {}", stage.describe());
+    }
+}
+
+pub fn current_stage() -> Stage {
+    Stage::ALL[CURRENT_STAGE.load(Ordering::Relaxed) as usize]
+}
@@ -1,3 +1,4 @@
+use super::ffmpeg::quality_level;
 use super::Live;
 use crate::load_config;
 use async_trait::async_trait;
@@ -9,14 +10,27 @@ use reqwest_retry::RetryTransientMiddleware;
 use serde_json::json;
 use std::error::Error;
 use std::path::Path;
-use std::process::Command;
-use std::time::Duration;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// streamlink is killed when it has not printed the stream url by then.
+const STREAMLINK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Attempts per detection before reporting the failure to the caller.
+const STREAMLINK_ATTEMPTS: u32 = 2;
+/// Clips fetched for FillWithClips.
+const CLIP_LIMIT: u32 = 20;
+
+/// Set while a skipped rerun was logged, so it is logged once per rerun.
+static RERUN_LOGGED: AtomicBool = AtomicBool::new(false);
 
 pub struct Twitch {
     pub channel_id: String,
     pub client: ClientWithMiddleware,
     pub oauth_token: String,
     pub proxy_region: String,
+    pub disable_ads: bool,
+    pub allow_rerun: bool,
 }
 
 #[async_trait]
@@ -29,6 +43,7 @@ impl Live for Twitch {
             Option<String>,
             Option<String>,
             Option<DateTime<Local>>,
+            bool,
         ),
         Box<dyn Error>,
     > {
@@ -56,12 +71,21 @@ impl Live for Twitch {
             .json()
             .await?;
         // println!("{:?}", res);
-        if res["data"]["user"]["stream"]["type"] == "live" {
+        let stream_type = &res["data"]["user"]["stream"]["type"];
+        // streamlink plays reruns as well, so the stream type tells them from lives
+        if stream_type == "rerun" && !self.allow_rerun {
+            if !RERUN_LOGGED.swap(true, Ordering::Relaxed) {
+                tracing::info!("{} 正在播放重播，跳过（AllowRerun）", self.channel_id);
+            }
+            return Ok((false, None, None, None, false));
+        }
+        RERUN_LOGGED.store(false, Ordering::Relaxed);
+        if stream_type == "live" || stream_type == "rerun" {
             let m3u8_url = self.get_streamlink_url()?;
             let title = get_twitch_live_title(&self.channel_id, self.client.clone()).await?;
-            Ok((true, Some(m3u8_url), Some(title), None))
+            Ok((true, Some(m3u8_url), Some(title), None, false))
         } else {
-            Ok((false, None, None, None))
+            Ok((false, None, None, None, false))
         }
     }
 
@@ -76,12 +100,16 @@ impl Twitch {
         oauth_token: String,
         client: ClientWithMiddleware,
         proxy_region: String,
-    ) -> impl Live {
+        disable_ads: bool,
+        allow_rerun: bool,
+    ) -> Self {
         Twitch {
             channel_id: channel_id.to_string(),
             client,
             oauth_token,
             proxy_region,
+            disable_ads,
+            allow_rerun,
         }
     }
     pub fn get_proxy_url(&self) -> Result<String, &'static str> {
@@ -105,8 +133,85 @@ impl Twitch {
         }
     }
     pub fn get_streamlink_url(&self) -> Result<String, Box<dyn Error>> {
+        let mut last_error: Box<dyn Error> = "streamlink未运行".into();
+        for attempt in 1..=STREAMLINK_ATTEMPTS {
+            match self.run_streamlink() {
+                Ok(url) => return Ok(url),
+                Err(e) => {
+                    tracing::warn!(
+                        "streamlink获取直播流失败 ({}/{}): {}",
+                        attempt,
+                        STREAMLINK_ATTEMPTS,
+                        e.to_string().trim()
+                    );
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Returns the slugs of the channel's most viewed clips of the last week.
+    pub async fn get_recent_clips(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let j = json!(
+            {
+                "operationName":"ClipsCards__User",
+                "variables":{
+                    "login":&self.channel_id,
+                    "limit":CLIP_LIMIT,
+                    "criteria":{"filter":"LAST_WEEK"},
+                },
+                "extensions":{
+                    "persistedQuery":{
+                        "version":1,
+                        "sha256Hash":"b73ad2bfaecfd30a9e6c28fada15bd97032c83ec77a0440766a56fe0bd632777"
+                    }
+                }
+            }
+        );
+        let res: serde_json::Value = self
+            .client
+            .post("https://gql.twitch.tv/gql")
+            .header("Client-ID", "kimne78kx3ncx6brgo4mv6wki5h1ko")
+            .json(&j)
+            .send()
+            .await?
+            .json()
+            .await?;
+        Ok(res["data"]["user"]["clips"]["edges"]
+            .as_array()
+            .map(|edges| {
+                edges
+                    .iter()
+                    .filter_map(|edge| edge["node"]["slug"].as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn get_clip_url(&self, slug: &str) -> Result<String, Box<dyn Error>> {
         let proxy_url = self.get_proxy_url()?;
         let output = Command::new("streamlink")
+            .arg(proxy_url)
+            .arg("--stream-url")
+            .arg(format!("https://clips.twitch.tv/{}", slug))
+            .arg("best")
+            .output()?;
+        if output.status.success() {
+            Ok(String::from_utf8(output.stdout)?.trim().to_string())
+        } else {
+            Err(String::from_utf8(output.stderr)?.into())
+        }
+    }
+
+    fn run_streamlink(&self) -> Result<String, Box<dyn Error>> {
+        let proxy_url = self.get_proxy_url()?;
+        let mut command = Command::new("streamlink");
+        if self.disable_ads {
+            command.arg("--twitch-disable-ads");
+        }
+        let mut child = command
             // .arg("--twitch-proxy-playlist=https://lb-eu3.cdn-perfprod.com,https://lb-eu.cdn-perfprod.com,https://lb-eu2.cdn-perfprod.com,https://lb-eu4.cdn-perfprod.com,https://lb-eu5.cdn-perfprod.com")
             // .arg("--twitch-proxy-playlist=https://lb-na.cdn-perfprod.com,https://lb-eu3.cdn-perfprod.com,https://lb-eu.cdn-perfprod.com,https://lb-eu2.cdn-perfprod.com,https://lb-eu4.cdn-perfprod.com,https://lb-eu5.cdn-perfprod.com")
             .arg(proxy_url)
@@ -119,8 +224,27 @@ impl Twitch {
                 "https://www.twitch.tv/{}",
                 self.channel_id.as_str().replace("\"", "")
             ))
-            .arg("best")
-            .output()?;
+            .arg(match quality_level() {
+                0 => "best",
+                1 => "720p60,720p,best",
+                _ => "480p,480p30,worst",
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let started = Instant::now();
+        while child.try_wait()?.is_none() {
+            if started.elapsed() >= STREAMLINK_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(
+                    format!("streamlink超过{}秒未返回", STREAMLINK_TIMEOUT.as_secs()).into(),
+                );
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        let output = child.wait_with_output()?;
 
         if output.status.success() {
             let url = String::from_utf8(output.stdout)?.trim().to_string();
@@ -148,9 +272,11 @@ pub async fn get_twitch_live_status(channel_id: &str) -> Result<bool, Box<dyn st
         cfg.twitch.oauth_token.clone(),
         client,
         cfg.twitch.proxy_region.clone(),
+        cfg.twitch.disable_ads,
+        cfg.twitch.allow_rerun,
     );
 
-    let (is_live, _, _, _) = twitch.get_status().await?;
+    let (is_live, _, _, _, _) = twitch.get_status().await?;
 
     Ok(is_live)
 }
@@ -187,3 +313,64 @@ pub async fn get_twitch_live_title(
         .unwrap()
         .to_string())
 }
+
+/// Turns a Twitch channel URL such as `https://www.twitch.tv/kamito` into the login
+/// `kamito`. A login is returned as it is.
+pub fn resolve_twitch_channel(input: &str) -> String {
+    let input = input.trim();
+    let path = input
+        .split_once("twitch.tv/")
+        .map_or(input, |(_, path)| path);
+    path.split(['/', '?', '#'])
+        .next()
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Returns true when a Twitch user with the login `channel_id` exists.
+pub async fn twitch_channel_exists(
+    channel_id: &str,
+    client: ClientWithMiddleware,
+) -> Result<bool, Box<dyn Error>> {
+    let j = json!(
+        {
+            "operationName":"StreamMetadata",
+            "variables":{
+                "channelLogin":channel_id,
+            },
+            "extensions":{
+                "persistedQuery":{
+                    "version":1,
+                    "sha256Hash":"1c719a40e481453e5c48d9bb585d971b8b372f8ebb105b17076722264dfa5b3e"
+                }
+            }
+        }
+    );
+    let res: serde_json::Value = client
+        .post("https://gql.twitch.tv/gql")
+        .header("Client-ID", "kimne78kx3ncx6brgo4mv6wki5h1ko")
+        .json(&j)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(!res["data"]["user"].is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_twitch_channel_strips_url() {
+        assert_eq!(resolve_twitch_channel("somechannel"), "somechannel");
+        assert_eq!(
+            resolve_twitch_channel("https://www.twitch.tv/somechannel/videos?filter=all"),
+            "somechannel"
+        );
+        assert_eq!(
+            resolve_twitch_channel(" twitch.tv/somechannel "),
+            "somechannel"
+        );
+    }
+}
@@ -1,14 +1,205 @@
 use async_trait::async_trait;
 // use reqwest_middleware::ClientWithMiddleware;
 use super::danmaku::get_channel_id;
+use super::ffmpeg::quality_level;
 use super::twitch::get_twitch_live_status;
 use super::Live;
 use crate::config::load_config;
 use chrono::{DateTime, Local};
 use regex::Regex;
 use std::error::Error; // Ensure this is included
+use std::fs;
 use std::path::Path;
 use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Why a YouTube live cannot be rebroadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YoutubeRestriction {
+    /// Members-only live (会限).
+    MembersOnly,
+    /// Paid premiere or paid live.
+    Paid,
+}
+
+impl YoutubeRestriction {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            YoutubeRestriction::MembersOnly => "会限直播",
+            YoutubeRestriction::Paid => "付费直播/首映",
+        }
+    }
+}
+
+/// Recognizes a members-only or paid live from a Holodex `topic_id` or a yt-dlp error message.
+pub fn classify_youtube_restriction(text: &str) -> Option<YoutubeRestriction> {
+    let text = text.to_lowercase();
+    if text.contains("membersonly")
+        || text.contains("members-only")
+        || text.contains("available to this channel's members")
+        || text.contains("join this channel to get access")
+    {
+        Some(YoutubeRestriction::MembersOnly)
+    } else if text.contains("requires payment")
+        || text.contains("available for purchase")
+        || text.contains("purchase this video")
+        || text.contains("rent this")
+        || text.contains("premium members")
+    {
+        Some(YoutubeRestriction::Paid)
+    } else {
+        None
+    }
+}
+
+/// How long yt-dlp is not asked again about a channel whose live turned out to be restricted.
+const RESTRICTED_SKIP: Duration = Duration::from_secs(30 * 60);
+
+/// Channels with a restricted live and when they may be checked with yt-dlp again.
+static RESTRICTED_CHANNELS: Mutex<Vec<(String, Instant, YoutubeRestriction)>> =
+    Mutex::new(Vec::new());
+/// Channels seen with an upcoming live, used by `OnlyScheduled`.
+const SCHEDULED_FILE: &str = "scheduled_channels.json";
+/// Upcoming lives seen longer ago than this no longer allow a rebroadcast.
+const SCHEDULED_KEEP_DAYS: i64 = 7;
+
+fn load_scheduled() -> Vec<(String, DateTime<Local>)> {
+    fs::read_to_string(SCHEDULED_FILE)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_scheduled(scheduled: &[(String, DateTime<Local>)]) {
+    match serde_json::to_string_pretty(scheduled) {
+        Ok(content) => {
+            if let Err(e) = fs::write(SCHEDULED_FILE, content) {
+                tracing::error!("写入{}失败: {}", SCHEDULED_FILE, e);
+            }
+        }
+        Err(e) => tracing::error!("序列化预告记录失败: {}", e),
+    }
+}
+
+/// Remembers that `channel_id` announced an upcoming live.
+pub fn remember_scheduled(channel_id: &str) {
+    let mut scheduled = load_scheduled();
+    scheduled.retain(|(id, _)| id != channel_id);
+    scheduled.push((channel_id.to_string(), Local::now()));
+    save_scheduled(&scheduled);
+}
+
+/// Returns true when `channel_id` announced an upcoming live in the last few days.
+pub fn was_scheduled(channel_id: &str) -> bool {
+    let oldest = Local::now() - chrono::Duration::days(SCHEDULED_KEEP_DAYS);
+    load_scheduled()
+        .iter()
+        .any(|(id, seen)| id == channel_id && *seen > oldest)
+}
+
+/// Forgets the upcoming live of `channel_id` once it has been rebroadcast.
+pub fn forget_scheduled(channel_id: &str) {
+    let mut scheduled = load_scheduled();
+    let len = scheduled.len();
+    scheduled.retain(|(id, _)| id != channel_id);
+    if scheduled.len() != len {
+        save_scheduled(&scheduled);
+    }
+}
+
+/// Without a Holodex key, yt-dlp is still run this often even when the RSS feed shows
+/// nothing new, since the feed can lag behind a live by several minutes.
+const RSS_FALLBACK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// How long after a new feed entry yt-dlp keeps being asked, as an upcoming live
+/// shows up in the feed before it starts.
+const RSS_WATCH: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// What the RSS pre-check knows about a channel.
+struct RssState {
+    channel_id: String,
+    /// Video ids of the last fetched feed.
+    seen: Vec<String>,
+    /// The last yt-dlp check, see [`RSS_FALLBACK_INTERVAL`].
+    last_check: Instant,
+    watch_until: Option<Instant>,
+    /// The last yt-dlp check found the channel live or with an upcoming live.
+    active: bool,
+}
+
+static RSS_STATES: Mutex<Vec<RssState>> = Mutex::new(Vec::new());
+
+/// Fetches the video ids of the channel's RSS feed, newest first.
+async fn fetch_feed_video_ids(
+    channel_id: &str,
+    proxy: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let feed = builder
+        .build()?
+        .get(format!(
+            "https://www.youtube.com/feeds/videos.xml?channel_id={}",
+            channel_id
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let re = Regex::new(r"<yt:videoId>([^<]+)</yt:videoId>")?;
+    Ok(re
+        .captures_iter(&feed)
+        .map(|captures| captures[1].to_string())
+        .collect())
+}
+
+/// Decides from the free RSS feed whether yt-dlp needs to check the channel, to save
+/// yt-dlp runs when there is no Holodex key. Errs on the side of checking.
+async fn rss_suggests_check(channel_id: &str, proxy: Option<&str>) -> bool {
+    let ids = fetch_feed_video_ids(channel_id, proxy).await;
+    let mut states = RSS_STATES.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(state) = states.iter_mut().find(|s| s.channel_id == channel_id) else {
+        states.push(RssState {
+            channel_id: channel_id.to_string(),
+            seen: ids.unwrap_or_default(),
+            last_check: Instant::now(),
+            watch_until: None,
+            active: false,
+        });
+        return true;
+    };
+    match ids {
+        Ok(ids) => {
+            if ids.iter().any(|id| !state.seen.contains(id)) {
+                tracing::debug!("频道 {} 的RSS有新视频，使用yt-dlp检测", channel_id);
+                state.watch_until = Some(Instant::now() + RSS_WATCH);
+            }
+            state.seen = ids;
+        }
+        Err(e) => tracing::debug!("获取频道 {} 的RSS失败: {}", channel_id, e),
+    }
+    let check = state.active
+        || state
+            .watch_until
+            .is_some_and(|until| until > Instant::now())
+        || state.last_check.elapsed() >= RSS_FALLBACK_INTERVAL;
+    if check {
+        state.last_check = Instant::now();
+    }
+    check
+}
+
+/// Remembers whether yt-dlp found the channel live or upcoming, which keeps it checked.
+fn record_rss_result(channel_id: &str, active: bool) {
+    let mut states = RSS_STATES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(state) = states.iter_mut().find(|s| s.channel_id == channel_id) {
+        state.active = active;
+    }
+}
+
 pub struct Youtube {
     pub channel_name: String,
     pub channel_id: String,
@@ -24,6 +215,7 @@ impl Live for Youtube {
             Option<String>,
             Option<String>,
             Option<DateTime<Local>>,
+            bool,
         ),
         Box<dyn Error>,
     > {
@@ -32,7 +224,7 @@ impl Live for Youtube {
 }
 
 impl Youtube {
-    pub fn new(channel_name: &str, channel_id: &str, proxy: Option<String>) -> impl Live {
+    pub fn new(channel_name: &str, channel_id: &str, proxy: Option<String>) -> Self {
         Youtube {
             channel_name: channel_name.to_string(),
             channel_id: channel_id.to_string(),
@@ -49,6 +241,7 @@ pub async fn get_youtube_live_status(
         Option<String>,
         Option<String>,
         Option<DateTime<Local>>,
+        bool,
     ),
     Box<dyn Error>,
 > {
@@ -60,9 +253,20 @@ pub async fn get_youtube_live_status(
     let cfg = load_config(Path::new("YT/config.yaml"), Path::new("cookies.json"))?;
     let proxy = cfg.proxy.clone();
     let channel_name = &cfg.youtube.channel_name;
+    let Some(holodex_api_key) = cfg.holodex_api_key.clone().filter(|key| !key.is_empty()) else {
+        // No Holodex key: the RSS feed decides when yt-dlp is worth running
+        if !rss_suggests_check(channel_id, proxy.as_deref()).await {
+            return Ok((false, None, None, None, false));
+        }
+        let status = get_status_with_yt_dlp(channel_id, proxy, None).await;
+        if let Ok((is_live, _, _, scheduled_start, _)) = &status {
+            record_rss_result(channel_id, *is_live || scheduled_start.is_some());
+        }
+        return status;
+    };
     let response = client
         .get(&url)
-        .header("X-APIKEY", cfg.holodex_api_key.clone().unwrap())
+        .header("X-APIKEY", holodex_api_key)
         .send()
         .await?;
     if response.status().is_success() {
@@ -71,6 +275,7 @@ pub async fn get_youtube_live_status(
         if !videos.is_empty() {
             let mut vid = videos.last().unwrap();
             let mut flag = false;
+            let mut members_only = false;
             for video in videos.iter().rev() {
                 let cname = video.get("channel");
                 if cname.is_none() {
@@ -87,12 +292,16 @@ pub async fn get_youtube_live_status(
                     .contains(channel_name)
                 {
                     if let Some(topic_id) = video.get("topic_id") {
-                        if topic_id.as_str().unwrap().contains("membersonly") {
-                            // tracing::info!("频道 {} 正在进行会限直播", channel_name);
-                        } else {
-                            vid = video;
-                            flag = true;
-                            break;
+                        match classify_youtube_restriction(topic_id.as_str().unwrap()) {
+                            Some(YoutubeRestriction::MembersOnly) => {
+                                members_only |= video.get("status").unwrap() == "live";
+                            }
+                            Some(_) => {}
+                            None => {
+                                vid = video;
+                                flag = true;
+                                break;
+                            }
                         }
                     } else {
                         vid = video;
@@ -110,23 +319,28 @@ pub async fn get_youtube_live_status(
                         .ok_or("start_scheduled 不存在")?;
                     // 将时间字符串转换为DateTime<Local>
                     let start_time =
-                        DateTime::parse_from_rfc3339(&start_time_str)?.with_timezone(&Local);
+                        DateTime::parse_from_rfc3339(start_time_str)?.with_timezone(&Local);
                     if vid.get("title").is_some() {
                         let title = vid.get("title").unwrap();
                         // println!("计划开始时间: {}", start_time);
-                        return Ok((false, None, Some(title.to_string()), Some(start_time)));
+                        Ok((
+                            false,
+                            None,
+                            Some(title.to_string()),
+                            Some(start_time),
+                            false,
+                        ))
                     } else {
-                        return Ok((false, None, None, Some(start_time)));
+                        Ok((false, None, None, Some(start_time), false))
                     }
                 } else if status == "live" {
                     let tw_channel_id = get_channel_id("TW", channel_name).unwrap();
-                    if tw_channel_id.is_some() {
-                        if get_twitch_live_status(tw_channel_id.as_ref().unwrap())
+                    if tw_channel_id.is_some()
+                        && get_twitch_live_status(tw_channel_id.as_ref().unwrap())
                             .await
                             .unwrap()
-                        {
-                            return Ok((false, None, None, None));
-                        }
+                    {
+                        return Ok((false, None, None, None, false));
                     }
                     if let Some(title) = vid.get("title").and_then(|v| v.as_str()) {
                         // println!("title: {}", title);
@@ -136,13 +350,13 @@ pub async fn get_youtube_live_status(
                         return get_status_with_yt_dlp(channel_id, proxy, None).await;
                     }
                 } else {
-                    return Ok((false, None, None, None));
+                    Ok((false, None, None, None, false))
                 }
             } else {
-                return Ok((false, None, None, None));
+                Ok((false, None, None, None, members_only))
             }
         } else {
-            return Ok((false, None, None, None));
+            Ok((false, None, None, None, false))
         }
     } else {
         tracing::error!("Holodex获取直播状态失败，使用yt-dlp获取");
@@ -181,7 +395,7 @@ pub async fn get_youtube_live_title(channel_id: &str) -> Result<Option<String>,
                     .contains(channel_name)
                 {
                     if let Some(topic_id) = video.get("topic_id") {
-                        if topic_id.as_str().unwrap().contains("membersonly") {
+                        if classify_youtube_restriction(topic_id.as_str().unwrap()).is_some() {
                             // tracing::info!("频道 {} 正在进行会限直播", channel_name);
                         } else {
                             vid = video;
@@ -206,9 +420,9 @@ pub async fn get_youtube_live_title(channel_id: &str) -> Result<Option<String>,
                     .get("title")
                     .and_then(|t| t.as_str())
                     .map(|s| s.split(" 202").next().unwrap_or(s).to_string());
-                return Ok(title);
+                Ok(title)
             } else {
-                return Ok(None);
+                Ok(None)
             }
         } else {
             Ok(None)
@@ -243,15 +457,35 @@ async fn get_status_with_yt_dlp(
         Option<String>,
         Option<String>,
         Option<DateTime<Local>>,
+        bool,
     ),
     Box<dyn Error>,
 > {
+    {
+        let mut restricted = RESTRICTED_CHANNELS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        restricted.retain(|(_, until, _)| *until > Instant::now());
+        if let Some((_, _, restriction)) = restricted.iter().find(|(id, _, _)| id == channel_id) {
+            let members_only = *restriction == YoutubeRestriction::MembersOnly;
+            return Ok((false, None, None, None, members_only));
+        }
+    }
     let mut command = Command::new("yt-dlp");
     if let Some(proxy) = proxy.clone() {
         command.arg("--proxy");
         command.arg(proxy);
     }
     command.arg("-g");
+    match quality_level() {
+        0 => {}
+        1 => {
+            command.arg("-f").arg("best[height<=720]/best");
+        }
+        _ => {
+            command.arg("-f").arg("best[height<=480]/worst");
+        }
+    }
 
     command.arg(format!(
         "https://www.youtube.com/channel/{}/live",
@@ -263,52 +497,225 @@ async fn get_status_with_yt_dlp(
     let stderr = String::from_utf8_lossy(&output.stderr);
     // println!("yt-dlp -g {}", stderr);
     if stderr.contains("ERROR: [youtube]") {
-        // Check for scheduled start time in stderr
-        if let Some(captures) =
-            Regex::new(r"This live event will begin in (\d+) minutes")?.captures(&stderr)
-        {
-            let minutes: i64 = captures[1].parse()?;
-            let start_time = chrono::Local::now() + chrono::Duration::minutes(minutes);
-            if title.is_some() {
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            } else {
-                let title = get_youtube_live_title(channel_id).await?;
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            }
-        }
-        if let Some(captures) =
-            Regex::new(r"This live event will begin in (\d+) hours")?.captures(&stderr)
-        {
-            let hours: i64 = captures[1].parse()?;
-            let start_time = chrono::Local::now() + chrono::Duration::hours(hours);
-            if title.is_some() {
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            } else {
-                let title = get_youtube_live_title(channel_id).await?;
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            }
+        if let Some(restriction) = classify_youtube_restriction(&stderr) {
+            tracing::info!(
+                "频道 {} 正在进行{}，无法转播，{} 分钟内不再检测",
+                channel_id,
+                restriction.describe(),
+                RESTRICTED_SKIP.as_secs() / 60
+            );
+            RESTRICTED_CHANNELS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((
+                    channel_id.to_string(),
+                    Instant::now() + RESTRICTED_SKIP,
+                    restriction,
+                ));
+            let members_only = restriction == YoutubeRestriction::MembersOnly;
+            return Ok((false, None, None, None, members_only));
         }
+        // Check for scheduled start time in stderr
         if let Some(captures) =
-            Regex::new(r"This live event will begin in (\d+) days")?.captures(&stderr)
+            Regex::new(r"This live event will begin in (\d+) (minutes|hours|days)")?
+                .captures(&stderr)
         {
-            let days: i64 = captures[1].parse()?;
-            let start_time = chrono::Local::now() + chrono::Duration::days(days);
-            if title.is_some() {
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            } else {
-                let title = get_youtube_live_title(channel_id).await?;
-                return Ok((false, None, title, Some(start_time))); // Return scheduled start time
-            }
+            let amount: i64 = captures[1].parse()?;
+            let unit = match &captures[2] {
+                "minutes" => chrono::Duration::minutes(1),
+                "hours" => chrono::Duration::hours(1),
+                _ => chrono::Duration::days(1),
+            };
+            let estimate = chrono::Local::now() + unit * amount as i32;
+            // "in 3 hours" can be off by most of an hour, the video knows the exact time
+            let start_time = precise_scheduled_start(channel_id, proxy.as_deref(), estimate, unit)
+                .unwrap_or(estimate);
+            let title = match title {
+                Some(title) => Some(title),
+                None => get_youtube_live_title(channel_id).await?,
+            };
+            return Ok((false, None, title, Some(start_time), false)); // Return scheduled start time
         }
-        return Ok((false, None, None, None)); // Channel is not live and no scheduled time
+        return Ok((false, None, None, None, false)); // Channel is not live and no scheduled time
     } else if Regex::new(r"https://.*\.m3u8").unwrap().is_match(&stdout) {
         if title.is_some() {
-            return Ok((true, Some(stdout.to_string()), title, None)); // Channel is currently live
+            return Ok((true, Some(stdout.to_string()), title, None, false)); // Channel is currently live
         } else {
             let title = get_youtube_live_title(channel_id).await?;
-            return Ok((true, Some(stdout.to_string()), title, None)); // Channel is currently live
+            return Ok((true, Some(stdout.to_string()), title, None, false)); // Channel is currently live
         }
     }
 
     Err("Unexpected output from yt-dlp".into())
 }
+
+/// Exact scheduled starts found by [`precise_scheduled_start`], by channel id.
+static SCHEDULED_STARTS: Mutex<Vec<(String, DateTime<Local>)>> = Mutex::new(Vec::new());
+
+/// Exact scheduled start of the channel's upcoming live, from the `release_timestamp`
+/// yt-dlp reads off the video. Used when Holodex is not available, as yt-dlp's error
+/// only gives a rounded "will begin in" (`estimate`, rounded to `unit`). The time is
+/// kept per channel and only fetched again when the estimate moves away from it,
+/// i.e. when the live was rescheduled.
+fn precise_scheduled_start(
+    channel_id: &str,
+    proxy: Option<&str>,
+    estimate: DateTime<Local>,
+    unit: chrono::Duration,
+) -> Option<DateTime<Local>> {
+    let cached = SCHEDULED_STARTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(id, _)| id == channel_id)
+        .map(|(_, start)| *start);
+    if let Some(start) = cached.filter(|start| (*start - estimate).abs() <= unit) {
+        return Some(start);
+    }
+    let mut command = Command::new("yt-dlp");
+    if let Some(proxy) = proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    let output = command
+        .arg("--ignore-no-formats-error")
+        .arg("--print")
+        .arg("release_timestamp")
+        .arg(format!(
+            "https://www.youtube.com/channel/{}/live",
+            channel_id
+        ))
+        .output()
+        .ok()?;
+    let timestamp: i64 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()?;
+    let start = DateTime::from_timestamp(timestamp, 0)?.with_timezone(&Local);
+    let mut starts = SCHEDULED_STARTS.lock().unwrap_or_else(|e| e.into_inner());
+    starts.retain(|(id, _)| id != channel_id);
+    starts.push((channel_id.to_string(), start));
+    Some(start)
+}
+
+/// Channel ids found by [`resolve_youtube_channel`] for what was configured, so that
+/// yt-dlp is not asked again on every config reload.
+static RESOLVED_CHANNELS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Turns what was configured as a YouTube channel into its `UC...` channel id: the id
+/// itself, an `@handle`, a channel URL (`/channel/`, `/@handle`, `/c/`) or the URL
+/// of one of its videos. Everything but an id is looked up once with yt-dlp.
+pub fn resolve_youtube_channel(input: &str, proxy: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let input = input.trim();
+    let channel_id =
+        Regex::new(r"^(?:https?://(?:www\.|m\.)?youtube\.com/channel/)?(UC[\w-]{22})(?:[/?].*)?$")
+            .unwrap();
+    if let Some(captures) = channel_id.captures(input) {
+        return Ok(captures[1].to_string());
+    }
+    if let Some((_, id)) = RESOLVED_CHANNELS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(configured, _)| configured == input)
+    {
+        return Ok(id.clone());
+    }
+    let url = if input.starts_with('@') {
+        format!("https://www.youtube.com/{}", input)
+    } else if input.starts_with("http")
+        || input.contains("youtube.com/")
+        || input.contains("youtu.be/")
+    {
+        input.to_string()
+    } else {
+        return Err(format!(
+            "无法识别的YouTube频道: {}，请填写频道ID、@handle或链接",
+            input
+        )
+        .into());
+    };
+    let mut command = Command::new("yt-dlp");
+    if let Some(proxy) = proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    let output = command
+        .arg("--ignore-no-formats-error")
+        .arg("--playlist-items")
+        .arg("1")
+        .arg("--print")
+        .arg("channel_id")
+        .arg(&url)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some(id) = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("UC"))
+    else {
+        return Err(format!("yt-dlp 无法从 {} 解析出频道ID", input).into());
+    };
+    tracing::info!("YouTube频道 {} 的ID为 {}", input, id);
+    RESOLVED_CHANNELS
+        .lock()
+        .unwrap()
+        .push((input.to_string(), id.to_string()));
+    Ok(id.to_string())
+}
+
+/// Returns true when the YouTube channel page of `channel_id` exists.
+pub async fn youtube_channel_exists(
+    channel_id: &str,
+    proxy: Option<&str>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let res = builder
+        .build()?
+        .get(format!("https://www.youtube.com/channel/{}", channel_id))
+        .send()
+        .await?;
+    Ok(res.status().is_success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_youtube_restriction_recognizes_members_and_paid() {
+        assert_eq!(
+            classify_youtube_restriction("membersonly"),
+            Some(YoutubeRestriction::MembersOnly)
+        );
+        assert_eq!(
+            classify_youtube_restriction("Join this channel to get access to members-only content"),
+            Some(YoutubeRestriction::MembersOnly)
+        );
+        assert_eq!(
+            classify_youtube_restriction("This video requires payment to watch"),
+            Some(YoutubeRestriction::Paid)
+        );
+        assert_eq!(
+            classify_youtube_restriction("Thanks for your purchase of merch!"),
+            None
+        );
+        assert_eq!(classify_youtube_restriction("singing"), None);
+    }
+
+    #[test]
+    fn resolve_youtube_channel_accepts_ids_and_channel_urls() {
+        let id = "UCabcdefghijklmnopqrstuv";
+        assert_eq!(resolve_youtube_channel(id, None).unwrap(), id);
+        assert_eq!(
+            resolve_youtube_channel(
+                &format!("https://www.youtube.com/channel/{}/live", id),
+                None
+            )
+            .unwrap(),
+            id
+        );
+        assert!(resolve_youtube_channel("not a channel", None).is_err());
+    }
+}
@@ -254,7 +254,11 @@ select_channel_id() {
         channel_name="ぶいすぽっ!【公式】"
         ;;
     0)
-        read -p "Enter custom YouTube Channel ID: " chid
+        while true; do
+            read -p "Enter custom YouTube Channel ID: " chid
+            "$BASE_DIR/bilistream" check-channel YT "$chid" && break
+            echo "Channel not found, please try again."
+        done
         read -p "Enter custom Channel Name: " channel_name
         read -p "Whether to add this channel to the ./YT/YT_channels.txt? (y/N): " add_choice
         add_choice=${add_choice:-N} # Default to 'N' if input is empty
@@ -367,7 +371,11 @@ select_twitch_id() {
         ;;
     0)
 
-        read -p "Enter Twitch ID: " channel_id
+        while true; do
+            read -p "Enter Twitch ID: " channel_id
+            "$BASE_DIR/bilistream" check-channel TW "$channel_id" && break
+            echo "Channel not found, please try again."
+        done
         read -p "Enter Channel Name: " channel_name
         read -p "Whether to add this channel to the ./TW/TW_channels.txt? (y/N): " add_choice
         add_choice=${add_choice:-N} # Default to 'N' if input is empty